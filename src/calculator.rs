@@ -1,31 +1,181 @@
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::thread;
+
 use crate::utils::logger::LOGGER;
+use crate::utils::storage;
+use crate::constants::{CONSTANTS, find_constant};
+use crate::error::{CalcError, ErrorKind, Span};
+use crate::functions::{AngleMode, FUNCTIONS, factorial, find_function};
+use crate::number::{ComplexDisplay, FractionDisplay, Number, NumberMode, rational_to_decimal, split_mixed};
+use crate::parser::{BinaryOp, Expr, ExprKind, ImplicitMultiplication, NameKind, Parser, PostfixOp, answer_index, is_identifier_char};
+use crate::programmer::{self, Signedness, WordSize};
+use crate::tokens::{Token, to_text, tokenize};
+use bigdecimal::BigDecimal;
+use num_bigint::BigInt;
+use num_complex::Complex64;
+use num_rational::BigRational;
+use num_traits::{Signed, Zero};
 
 
-const OPERATORS: &str = "+-×÷";
+const OPERATORS: &str = "+-×÷^";
+// Multi-character operators, all at the level of `×`/`÷`
+const LONG_OPERATORS: &[&str] = &["mod", "rem", "//", "%%"];
+// Longest first, so `2xor` ends with `xor` and not `or`
+const PROGRAMMER_OPERATORS: &[&str] = &["xor", "ror", "rol", "and", "not", "mod", "rem", "or", "<<", ">>", "//", "%%"];
 const OPEN_PAREN: char = '(';
 const CLOSE_PAREN: char = ')';
+const DECIMAL_DISPLAY_PLACES: i64 = 16;
+const MAX_PLAIN_DIGITS: i64 = 40;
+const SCIENTIFIC_DIGITS: u64 = 16;
+const COMPLEX_NOISE: f64 = 1e-12;
+const ASSIGN: char = '=';
+const ARGUMENT_SEPARATOR: char = ',';
+const HISTORY_REFERENCE: char = '#';
+const VARIABLES_FILE: &str = "variables";
+const FUNCTIONS_FILE: &str = "functions";
+const MEMORY_FILE: &str = "memory";
+// The slot MC, MR, M+, M− and MS work on
+const MEMORY_REGISTER: &str = "M";
+const MEMORY_KEY_PREFIX: &str = "memory-";
+const MAX_CALL_DEPTH: usize = 256;
+// Each nested node costs native stack, up to 9 KB of it in a debug build
+const MAX_NESTING_DEPTH: usize = 2048;
+const EVALUATION_STACK_SIZE: usize = 64 * 1024 * 1024;
+const MAX_UNDO_STEPS: usize = 100;
+// Names the parser already gives a meaning to
+const RESERVED_NAMES: &[&str] = &["i", "rad", "grad", "if", "mod", "rem", "and", "or", "xor", "not", "rol", "ror", "in", "to"];
+// `255 in hex`, `x to base 3`
+const CONVERSION_WORDS: &[&str] = &["in", "to"];
+const RADIX_NAMES: &[&str] = &["hex", "bin", "oct", "dec"];
+const BASE_WORD: &str = "base";
+// Rows of the base panel; a base asked for with `to base N` is added below them
+const PANEL_BASES: &[(&str, u32)] = &[("DEC", 10), ("HEX", 16), ("BIN", 2), ("OCT", 8)];
+
+
+
+// How far the live result can be trusted while the expression is being typed
+#[derive(Clone, PartialEq)]
+pub enum Preview {
+    Valid,
+    // Pending input such as a trailing operator or an open bracket; the last good value stays up
+    Incomplete,
+    Invalid(CalcError),
+}
 
 
 
 pub struct Calculator {
-    expression: String,
+    // Tokens up to the caret; every input rule looks at the end of these
+    expression: Vec<Token>,
+    // Tokens after the caret, empty while typing at the end
+    after_cursor: Vec<Token>,
+    result: String,
+    preview: Preview,
+    // Value behind `result`, if there is one
+    shown_value: Option<Number>,
+    // Whether `shown_value` had to be wrapped to fit the word size
+    overflow: bool,
+    // Base asked for with `in hex`, if any
+    shown_radix: Option<u32>,
+    last_result: Number,
+    // Every successful `=` of the session, oldest first; `#1` is the first
+    history: Vec<Number>,
+    // History number of the result on screen, if it was just added
+    shown_entry: Option<usize>,
+    should_reset_expression: bool,
+    auto_close_parentheses: bool,
+    // Integers only, with radix literals and bitwise operators
+    programmer_mode: bool,
+    word_size: WordSize,
+    signedness: Signedness,
+    angle_mode: AngleMode,
+    number_mode: NumberMode,
+    implicit_multiplication: ImplicitMultiplication,
+    fraction_display: FractionDisplay,
+    complex_display: ComplexDisplay,
+    variables: BTreeMap<String, Number>,
+    functions: BTreeMap<String, UserFunction>,
+    // Memory slots by name; the M keys use `M`
+    memory: BTreeMap<String, Number>,
+    // Edits to go back to, latest last, and the ones undone since
+    undo_stack: Vec<EditState>,
+    redo_stack: Vec<EditState>,
+    // The state the next edit is measured against
+    settled: Option<EditState>,
+    // Whether variables, functions and memory are written to the data files; off for throwaway calculators in tests
+    persistent: bool,
+}
+
+
+
+struct UserFunction {
+    params: Vec<String>,
+    body: String,
+    tree: Expr,
+}
+
+
+
+// What undo brings back: the expression and caret, and the result shown for it
+#[derive(Clone)]
+struct EditState {
+    expression: Vec<Token>,
+    after_cursor: Vec<Token>,
     result: String,
-    last_result: f64,
+    preview: Preview,
+    shown_value: Option<Number>,
+    overflow: bool,
+    shown_radix: Option<u32>,
+    shown_entry: Option<usize>,
+    last_result: Number,
     should_reset_expression: bool,
-    parentheses_count: i32,
+}
+
+
+
+struct Evaluation {
+    value: Number,
+    // Programmer mode had to wrap something on the way
+    overflow: bool,
+    radix: Option<u32>,
 }
 
 
 
 impl Calculator {
     pub fn new() -> Self {
-        Self {
-            expression: String::new(),
+        let mut calculator = Self {
+            expression: Vec::new(),
+            after_cursor: Vec::new(),
             result: String::from("0"),
-            last_result: 0.0,
+            preview: Preview::Valid,
+            shown_value: None,
+            overflow: false,
+            shown_radix: None,
+            last_result: Number::zero(NumberMode::Precise),
+            history: Vec::new(),
+            shown_entry: None,
             should_reset_expression: false,
-            parentheses_count: 0,
-        }
+            auto_close_parentheses: true,
+            programmer_mode: false,
+            word_size: WordSize::Bits64,
+            signedness: Signedness::Signed,
+            angle_mode: AngleMode::Radians,
+            number_mode: NumberMode::Precise,
+            implicit_multiplication: ImplicitMultiplication::Tight,
+            fraction_display: FractionDisplay::Fraction,
+            complex_display: ComplexDisplay::Rectangular,
+            variables: BTreeMap::new(),
+            functions: BTreeMap::new(),
+            memory: BTreeMap::new(),
+            undo_stack: Vec::new(),
+            redo_stack: Vec::new(),
+            settled: None,
+            persistent: true,
+        };
+        calculator.settled = Some(calculator.edit_state());
+        calculator
     }
 
 
@@ -35,7 +185,13 @@ impl Calculator {
             self.should_reset_expression = false;
         }
         
-        self.expression.push_str(digit);
+        // Digits continue a number or a name like `log10`, and start a number anywhere else;
+        // after the Ans key they would quietly make it `ans2`
+        match self.expression.last_mut() {
+            Some(Token::Answer) => return,
+            Some(Token::Number(text) | Token::Name(text)) => text.push_str(digit),
+            _ => self.expression.push(Token::Number(digit.to_string())),
+        }
         self.update_result();
     }
 
@@ -46,58 +202,92 @@ impl Calculator {
             self.should_reset_expression = false;
         }
         
-        let mut can_add_decimal = true;
-        let mut i = self.expression.len();
-        
-        while i > 0 {
-            i -= 1;
-            let ch = self.expression.as_bytes()[i] as char;
-            if ch == '.' {
-                can_add_decimal = false;
-                break;
-            }
-            if !ch.is_ascii_digit() {
-                break;
-            }
+        // The number may go on past the caret: `1|.5`
+        if matches!(self.after_cursor.first(), Some(Token::Number(digits)) if digits.contains('.')) {
+            return;
         }
-        
-        if can_add_decimal {
-            if self.expression.is_empty() || 
-               OPERATORS.contains(self.expression.as_bytes().last().map_or('\0', |&b| b as char)) ||
-               self.expression.as_bytes().last().map_or(false, |&b| b as char == OPEN_PAREN) {
-                self.expression.push_str("0.");
-            } else {
-                self.expression.push('.');
-            }
-            self.update_result();
+        let starts_operand = self.can_start_operand();
+        match self.expression.last_mut() {
+            Some(token @ Token::Number(_)) if token.radix().is_some() => return,
+            Some(Token::Number(text)) if !text.contains('.') => text.push('.'),
+            Some(Token::Number(_)) => return,
+            _ if starts_operand => self.expression.push(Token::Number("0.".to_string())),
+            _ => return,
         }
+        self.update_result();
     }
 
 
     pub fn add_operator(&mut self, operator: &str) {
         if self.should_reset_expression {
-            self.expression = self.result.clone();
+            self.expression = self.tokens_of(&self.result_as_expression());
             self.should_reset_expression = false;
         }
         
         if !self.expression.is_empty() {
-            let last_char = char::from(*self.expression.as_bytes().last().unwrap());
-            
-            if OPERATORS.contains(last_char) {
-                self.expression.pop();
+            // Negative exponents and divisors: `2^-1`, `7mod-3`
+            if operator == "minus" && self.trailing_operator().is_some_and(|op| op == "^" || op.chars().count() > 1) {
+                self.expression.push(Token::Operator("-".to_string()));
+                self.update_result();
+                return;
             }
             
             let op_symbol = match operator {
                 "plus" => "+",
                 "minus" => "-",
                 "multiply" => "×",
-                "divide" => "÷",
+                // `//` typed on the keyboard is floor division; the ÷ key replaces ÷ like any other operator
+                "slash" if self.trailing_operator() == Some("÷") => "//",
+                "divide" | "slash" => "÷",
+                "power" => "^",
+                "mod" => "mod",
+                "rem" => "rem",
+                "floor-divide" => "//",
+                "and" | "or" | "xor" | "rol" | "ror" if self.programmer_mode => operator,
+                "shift-left" if self.programmer_mode => "<<",
+                "shift-right" if self.programmer_mode => ">>",
                 _ => return,
             };
             
-            self.expression.push_str(op_symbol);
+            if self.trailing_operator().is_some() {
+                self.expression.pop();
+            }
+            // The same goes for one just after the caret: `2|+3` and × make `2×3`; a prefix `not` stays
+            if matches!(self.after_cursor.first(), Some(Token::Operator(op)) if op != "not") {
+                self.after_cursor.remove(0);
+            }
+            self.expression.push(Token::Operator(op_symbol.to_string()));
         } else if operator == "minus" {
-            self.expression.push('-');
+            self.expression.push(Token::Operator("-".to_string()));
+        }
+        self.update_result();
+    }
+
+
+    // `not` is a prefix, so it goes where an operand would and leaves any operator before it alone
+    pub fn add_not(&mut self) {
+        if self.should_reset_expression {
+            self.expression.clear();
+            self.should_reset_expression = false;
+        }
+
+        if self.programmer_mode && self.can_start_operand() {
+            self.expression.push(Token::Operator("not".to_string()));
+            self.update_result();
+        }
+    }
+
+
+    // `0x`, `0b`, `0o`; the digits are typed after it
+    pub fn add_literal_prefix(&mut self, prefix: &str) {
+        if self.should_reset_expression {
+            self.expression.clear();
+            self.should_reset_expression = false;
+        }
+
+        if self.programmer_mode && self.can_start_operand() {
+            self.expression.push(Token::Number(prefix.to_string()));
+            self.update_result();
         }
     }
 
@@ -110,21 +300,20 @@ impl Calculator {
         
         match paren_type {
             "open-paren" => {
-                if self.expression.is_empty() || 
-                   OPERATORS.contains(self.expression.as_bytes().last().map_or('\0', |&b| b as char)) ||
-                   self.expression.as_bytes().last().map_or(false, |&b| b as char == OPEN_PAREN) {
-                    self.expression.push(OPEN_PAREN);
-                    self.parentheses_count += 1;
+                // A bracket right after a name makes a call, which backspace removes as a whole
+                if let Some(Token::Name(name)) = self.expression.last() {
+                    let function = Token::Function(name.clone());
+                    self.expression.pop();
+                    self.expression.push(function);
+                } else if self.can_start_operand() || self.can_multiply_implicitly() {
+                    self.expression.push(Token::OpenParen);
                 }
             },
             "close-paren" => {
-                if self.parentheses_count > 0 &&
-                   self.expression.as_bytes().last().map_or(false, |&b| {
-                       let c = b as char;
-                       c.is_ascii_digit() || c == '.' || c == CLOSE_PAREN
-                   }) {
-                    self.expression.push(CLOSE_PAREN);
-                    self.parentheses_count -= 1;
+                let after_operand = self.ends_with_operand() ||
+                    matches!(self.expression.last(), Some(Token::Number(text)) if text.ends_with('.'));
+                if self.open_parentheses() > 0 && after_operand {
+                    self.expression.push(Token::CloseParen);
                 }
             },
             _ => {}
@@ -134,321 +323,2199 @@ impl Calculator {
     }
 
 
-    pub fn calculate(&mut self) {
-        if !self.expression.is_empty() {
-            match self.evaluate_expression(&self.expression) {
-                Ok(result) => {
-                    if result.is_nan() || result.is_infinite() {
-                        self.result = "x64 Error".to_string();
-                    } else {
-                        self.last_result = result;
-                        self.result = format_number(result);
-                    }
-                    self.should_reset_expression = true;
-                },
-                Err(e) => {
-                    self.result = format!("Error: {}", e);
-                    self.should_reset_expression = true;
+    pub fn add_square(&mut self) {
+        if self.should_reset_expression {
+            self.expression = self.tokens_of(&self.result_as_expression());
+            self.should_reset_expression = false;
+        }
+
+        if self.ends_with_operand() {
+            self.expression.push(Token::Operator("^".to_string()));
+            self.expression.push(Token::Number("2".to_string()));
+            self.update_result();
+        }
+    }
+
+
+    pub fn add_function(&mut self, name: &str) {
+        if self.should_reset_expression {
+            self.expression.clear();
+            self.should_reset_expression = false;
+        }
+
+        if self.can_start_operand() || self.can_multiply_implicitly() {
+            self.expression.push(Token::Function(name.to_string()));
+            self.update_result();
+        }
+    }
+
+
+    // Keyboard entry of names, one letter at a time; case matters (`G` is not `g`)
+    pub fn add_letter(&mut self, letter: char) {
+        if self.should_reset_expression {
+            self.expression.clear();
+            self.should_reset_expression = false;
+        }
+
+        // After the Ans key only a conversion can follow: `ans in hex`
+        let starts_conversion = matches!(self.expression.last(), Some(Token::Answer)) &&
+                                CONVERSION_WORDS.iter().any(|word| word.starts_with(letter));
+        let starts_name = self.can_start_operand() || self.can_multiply_implicitly() || starts_conversion;
+        match self.expression.last_mut() {
+            Some(Token::Name(name)) => name.push(letter),
+            // Hex digits of `0xFF`
+            Some(token @ Token::Number(_)) if token.radix().is_some_and(|radix| letter.is_digit(radix)) => {
+                if let Token::Number(digits) = token {
+                    digits.push(letter);
                 }
-            }
+            },
+            // Letters right after a value are unit suffixes (`1.2rad`) or products (`2x`)
+            _ if starts_name => self.expression.push(Token::Name(letter.to_string())),
+            _ => return,
         }
+        self.split_word_operator();
+        self.update_result();
     }
 
 
-    pub fn backspace(&mut self) {
-        if !self.expression.is_empty() {
-            let last_char = char::from(*self.expression.as_bytes().last().unwrap());
-            if last_char == OPEN_PAREN {
-                self.parentheses_count -= 1;
-            } else if last_char == CLOSE_PAREN {
-                self.parentheses_count += 1;
-            }
+    pub fn add_percent(&mut self) {
+        if self.should_reset_expression {
+            self.expression = self.tokens_of(&self.result_as_expression());
+            self.should_reset_expression = false;
+        }
+
+        // A second `%` makes the `%%` remainder operator
+        if self.expression.last() == Some(&Token::Percent) {
             self.expression.pop();
+            self.expression.push(Token::Operator("%%".to_string()));
+            self.update_result();
+        } else if self.ends_with_operand() {
+            self.expression.push(Token::Percent);
             self.update_result();
         }
     }
 
 
-    pub fn clear(&mut self) {
-        self.expression.clear();
-        self.result = "0".to_string();
-        self.last_result = 0.0;
-        self.should_reset_expression = false;
-        self.parentheses_count = 0;
+    pub fn add_factorial(&mut self) {
+        if self.should_reset_expression {
+            self.expression = self.tokens_of(&self.result_as_expression());
+            self.should_reset_expression = false;
+        }
+
+        if self.ends_with_operand() && self.expression.last() != Some(&Token::Percent) {
+            self.expression.push(Token::Factorial);
+            self.update_result();
+        }
     }
 
 
-    pub fn get_expression(&self) -> String {
-        self.expression.clone()
+    pub fn add_degree_sign(&mut self) {
+        if self.should_reset_expression {
+            self.expression = self.tokens_of(&self.result_as_expression());
+            self.should_reset_expression = false;
+        }
+
+        if self.ends_with_operand() && matches!(self.expression.last(), Some(Token::Number(_) | Token::Name(_) | Token::CloseParen)) {
+            self.expression.push(Token::Degree);
+            self.update_result();
+        }
     }
 
 
-    pub fn get_result(&self) -> String {
-        self.result.clone()
+    pub fn toggle_angle_mode(&mut self) {
+        self.angle_mode = self.angle_mode.next();
+        self.refresh_result();
     }
 
 
-    fn update_result(&mut self) {
-        if self.expression.is_empty() {
-            self.result = "0".to_string();
-            return;
+    pub fn toggle_number_mode(&mut self) {
+        self.number_mode = self.number_mode.next();
+        if !self.should_reset_expression {
+            self.update_result();
         }
+    }
 
-        match self.evaluate_expression(&self.expression) {
-            Ok(result) => {
-                if result.is_nan() || result.is_infinite() {
-                    self.result = "0".to_string();
-                } else {
-                    self.result = format_number(result);
-                }
-            },
-            Err(_) => {
-                self.result = "0".to_string();
-            }
+
+    pub fn toggle_implicit_multiplication(&mut self) {
+        self.implicit_multiplication = self.implicit_multiplication.next();
+        self.reparse_functions();
+        if !self.should_reset_expression {
+            self.update_result();
         }
     }
 
 
-    fn evaluate_expression(&self, expr: &str) -> Result<f64, String> {
-        if expr.is_empty() {
-            return Ok(0.0);
+    pub fn toggle_auto_close_parentheses(&mut self) {
+        self.auto_close_parentheses = !self.auto_close_parentheses;
+        if !self.should_reset_expression {
+            self.update_result();
         }
-        
-        let last_char = char::from(*expr.as_bytes().last().unwrap());
-        if OPERATORS.contains(last_char) || last_char == OPEN_PAREN || last_char == '.' {
-            return Err("Incomplete expression".to_string());
+    }
+
+
+    pub fn toggle_programmer_mode(&mut self) {
+        self.programmer_mode = !self.programmer_mode;
+        self.reparse_functions();
+        if !self.should_reset_expression {
+            self.update_result();
         }
-        
-        let normalized = expr.replace('×', "*").replace('÷', "/");
-        self.parse_expression(&normalized)
     }
-    
 
-    fn parse_expression(&self, expr: &str) -> Result<f64, String> {
-        let expr = expr.replace(' ', "");
-        self.evaluate_string(&expr)
+
+    pub fn toggle_word_size(&mut self) {
+        self.word_size = self.word_size.next();
+        self.refresh_result();
     }
-    
 
-    fn evaluate_string(&self, expr: &str) -> Result<f64, String> {
-        if expr.is_empty() {
-            return Ok(0.0);
-        }
-        
-        let mut parser = ExpressionParser::new(expr);
-        parser.parse()
+
+    pub fn toggle_signedness(&mut self) {
+        self.signedness = self.signedness.next();
+        self.refresh_result();
     }
-}
 
 
+    pub fn toggle_fraction_display(&mut self) {
+        self.fraction_display = self.fraction_display.next();
+        self.refresh_result();
+    }
 
-struct ExpressionParser<'a> {
-    input: &'a [u8],
-    pos: usize,
-}
 
+    pub fn toggle_complex_display(&mut self) {
+        self.complex_display = self.complex_display.next();
+        self.refresh_result();
+    }
 
 
-impl<'a> ExpressionParser<'a> {
-    fn new(input: &'a str) -> Self {
-        Self {
-            input: input.as_bytes(),
-            pos: 0,
+    // `,` separates arguments inside a call and is a decimal comma everywhere else
+    pub fn add_separator(&mut self) {
+        if self.should_reset_expression || !self.in_call_arguments() {
+            self.add_decimal();
+            return;
+        }
+
+        if !self.can_start_operand() {
+            self.expression.push(Token::Separator);
+            self.update_result();
         }
     }
-    
 
-    fn parse(&mut self) -> Result<f64, String> {
-        let result = self.parse_expression()?;
-        if self.pos < self.input.len() {
-            return Err("Unexpected character".to_string());
+
+    // `x=` / `f(x,y)=`; only right after a fresh name or signature
+    pub fn add_assignment(&mut self) {
+        if self.can_assign() {
+            self.expression.push(Token::Assign);
+            self.update_result();
         }
-        Ok(result)
     }
-    
 
-    fn parse_expression(&mut self) -> Result<f64, String> {
-        let mut result = self.parse_term()?;
-        
-        while self.pos < self.input.len() {
-            match self.current_char() {
-                b'+' => {
-                    self.pos += 1;
-                    result += self.parse_term()?;
-                },
-                b'-' => {
-                    self.pos += 1;
-                    result -= self.parse_term()?;
-                },
-                _ => break,
-            }
+
+    // Typed `=`: starts a definition after a name or signature, completes `:=`, otherwise evaluates
+    pub fn add_equals_sign(&mut self) {
+        let completes_assign = !self.should_reset_expression && self.expression.last() == Some(&Token::Assign);
+        if self.can_assign() {
+            self.add_assignment();
+        } else if !completes_assign {
+            self.calculate();
         }
-        
-        Ok(result)
     }
-    
 
-    fn parse_term(&mut self) -> Result<f64, String> {
-        let mut result = self.parse_factor()?;
-        
-        while self.pos < self.input.len() {
-            match self.current_char() {
-                b'*' => {
-                    self.pos += 1;
-                    result *= self.parse_factor()?;
-                },
-                b'/' => {
-                    self.pos += 1;
-                    let divisor = self.parse_factor()?;
-                    if divisor == 0.0 {
-                        return Err("Division by zero".to_string());
-                    }
-                    result /= divisor;
-                },
-                _ => break,
-            }
+
+    fn can_assign(&self) -> bool {
+        let text = to_text(&self.expression);
+        !self.should_reset_expression && (is_variable_name(&text) || parse_signature(&text).is_some())
+    }
+
+
+    // Ans key: the latest result, usable anywhere an operand can go
+    pub fn add_answer(&mut self) {
+        if self.should_reset_expression {
+            self.expression.clear();
+            self.should_reset_expression = false;
+        }
+
+        if self.can_start_operand() || self.can_multiply_implicitly() {
+            self.expression.push(Token::Answer);
+            self.update_result();
         }
-        
-        Ok(result)
     }
-    
 
-    fn parse_factor(&mut self) -> Result<f64, String> {
-        if self.pos >= self.input.len() {
-            return Err("Unexpected end of expression".to_string());
+
+    // `#` before a history number: `#3`
+    pub fn add_history_reference(&mut self) {
+        if self.should_reset_expression {
+            self.expression.clear();
+            self.should_reset_expression = false;
         }
-        
-        match self.current_char() {
-            b'(' => {
-                self.pos += 1;
-                let result = self.parse_expression()?;
-                if self.pos >= self.input.len() || self.current_char() != b')' {
-                    return Err("Missing closing parenthesis".to_string());
-                }
-                self.pos += 1;
-                Ok(result)
-            },
-            b'-' => {
-                self.pos += 1;
-                Ok(-self.parse_factor()?)
-            },
-            b'+' => {
-                self.pos += 1;
-                self.parse_factor()
-            },
-            _ => self.parse_number(),
+
+        if self.can_start_operand() || self.can_multiply_implicitly() {
+            self.expression.push(Token::HistoryReference);
+            self.update_result();
         }
     }
-    
 
-    fn parse_number(&mut self) -> Result<f64, String> {
-        let start = self.pos;
-        let is_negative = if self.pos < self.input.len() && self.current_char() == b'-' {
-            self.pos += 1;
-            true
-        } else {
-            false
-        };
 
-        let has_digits = self.pos < self.input.len() && self.current_char().is_ascii_digit();
-        while self.pos < self.input.len() && self.current_char().is_ascii_digit() {
-            self.pos += 1;
+    // Variables panel and constants picker
+    pub fn add_name(&mut self, name: &str) {
+        if self.should_reset_expression {
+            self.expression.clear();
+            self.should_reset_expression = false;
         }
 
-        let has_decimal = if self.pos < self.input.len() && self.current_char() == b'.' {
-            self.pos += 1;
-            while self.pos < self.input.len() && self.current_char().is_ascii_digit() {
-                self.pos += 1;
-            }
-            true
-        } else {
-            false
-        };
+        if (self.can_start_operand() || self.can_multiply_implicitly()) &&
+           (self.variables.contains_key(name) || find_constant(name).is_some()) {
+            self.expression.push(Token::Name(name.to_string()));
+            self.update_result();
+        }
+    }
+
+
+    pub fn set_variable(&mut self, name: &str, value_expression: &str) -> Result<(), CalcError> {
+        if !is_variable_name(name) {
+            return Err(ErrorKind::InvalidVariableName(name.to_string()).into());
+        }
+
+        let value = self.evaluate_expression(value_expression)?;
+        self.store_variable(name, value)
+    }
+
 
-        let has_exponent = if self.pos < self.input.len() && (self.current_char() == b'e' || self.current_char() == b'E') {
-            self.pos += 1;
-            if self.pos < self.input.len() && (self.current_char() == b'+' || self.current_char() == b'-') {
-                self.pos += 1;
+    fn store_variable(&mut self, name: &str, value: Number) -> Result<(), CalcError> {
+        if !is_variable_name(name) {
+            return Err(ErrorKind::InvalidVariableName(name.to_string()).into());
+        }
+        if !value.is_finite() {
+            return Err(ErrorKind::ValueOutOfRange.into());
+        }
+
+        self.variables.insert(name.to_string(), value);
+        self.save_variables();
+        self.reparse_functions();
+        Ok(())
+    }
+
+
+    pub fn delete_variable(&mut self, name: &str) {
+        if self.variables.remove(name).is_some() {
+            self.save_variables();
+            self.reparse_functions();
+            if !self.should_reset_expression {
+                self.update_result();
             }
-            let exp_start = self.pos;
-            while self.pos < self.input.len() && self.current_char().is_ascii_digit() {
-                self.pos += 1;
+        }
+    }
+
+
+    pub fn load_variables(&mut self) {
+        for (name, value) in storage::load(VARIABLES_FILE) {
+            if let Err(e) = self.stored_value(&value).and_then(|value| self.store_variable(&name, value)) {
+                LOGGER.warn(&format!("Skipping stored variable {}: {}", name, e));
             }
-            exp_start != self.pos
-        } else {
-            false
-        };
+        }
+    }
 
-        if start == self.pos || (!has_digits && !has_exponent && has_decimal) {
-            return Err("Invalid number format".to_string());
+
+    fn save_variables(&self) {
+        if !self.persistent {
+            return;
         }
 
-        let number_str = std::str::from_utf8(&self.input[start..self.pos]).map_err(|_| "Invalid UTF-8".to_string())?;
-        let number = number_str.parse::<f64>().map_err(|_| "Invalid number".to_string())?;
-        Ok(if is_negative { -number } else { number })
+        let entries: Vec<(String, String)> = self.variables
+            .iter()
+            .map(|(name, value)| (name.clone(), value.to_stored()))
+            .collect();
+        storage::save(VARIABLES_FILE, &entries);
     }
-    
 
-    fn current_char(&self) -> u8 {
-        if self.pos < self.input.len() {
-            self.input[self.pos]
-        } else {
-            0
-        }
+
+    pub fn define_function(&mut self, signature: &str, body: &str) -> Result<(), CalcError> {
+        let (name, function) = self.parse_definition(signature, body)?;
+        self.functions.insert(name, function);
+        // The body was read before its own name was a function: `h(n-1)` would be Planck's constant times n-1
+        self.reparse_functions();
+        self.save_functions();
+        Ok(())
     }
-}
 
 
+    // `f(x,y)` and its body, read with the parameters in scope
+    fn parse_definition(&self, signature: &str, body: &str) -> Result<(String, UserFunction), CalcError> {
+        let (name, params) = parse_signature(signature)
+            .ok_or_else(|| ErrorKind::InvalidSignature(signature.to_string()))?;
+        let body = body.replace(' ', "");
+        if body.is_empty() {
+            return Err(ErrorKind::EmptyFunctionBody.into());
+        }
 
-fn format_number(num: f64) -> String {
-    if num.is_nan() {
-        return "Error".to_string();
+        let tree = self.parse(&body, &params)?;
+        Ok((name, UserFunction { params, body, tree }))
     }
-    if num.is_infinite() {
-        return if num.is_sign_positive() { "Infinity" } else { "-Infinity" }.to_string();
+
+
+    pub fn delete_function(&mut self, name: &str) {
+        if self.functions.remove(name).is_some() {
+            self.save_functions();
+            if !self.should_reset_expression {
+                self.update_result();
+            }
+        }
     }
 
-    if num.fract().abs() < 1e-10 && num.abs() < 1e15 {
-        return format!("{}", num as i64);
+
+    pub fn load_functions(&mut self) {
+        for (signature, body) in storage::load(FUNCTIONS_FILE) {
+            if let Err(e) = self.define_function(&signature, &body) {
+                LOGGER.warn(&format!("Skipping stored function {}: {}", signature, e));
+            }
+        }
     }
 
-    let formatted = format!("{:.8}", num);
-    let trimmed = formatted.trim_end_matches('0').trim_end_matches('.');
-    if trimmed.len() > 12 || num.abs() > 1e15 || num.abs() < 1e-4 {
-        format!("{:.6e}", num).trim_end_matches('0').trim_end_matches('.').to_string()
-    } else {
-        trimmed.to_string()
+
+    // Stored trees depend on which names are variables and on how `1/2x` reads, so they follow both
+    fn reparse_functions(&mut self) {
+        let trees: Vec<(String, Expr)> = self.functions
+            .iter()
+            .filter_map(|(name, function)| Some((name.clone(), self.parse(&function.body, &function.params).ok()?)))
+            .collect();
+        for (name, tree) in trees {
+            if let Some(function) = self.functions.get_mut(&name) {
+                function.tree = tree;
+            }
+        }
     }
-}
 
 
+    fn save_functions(&self) {
+        if !self.persistent {
+            return;
+        }
 
-pub fn handle_calculator_input(button_id: &str, calculator: &mut Calculator) {
-    match button_id {
-        "0" | "1" | "2" | "3" | "4" | "5" | "6" | "7" | "8" | "9" => {
-            calculator.add_digit(button_id);
+        let entries: Vec<(String, String)> = self.functions
+            .iter()
+            .map(|(name, function)| (format_signature(name, function), function.body.clone()))
+            .collect();
+        storage::save(FUNCTIONS_FILE, &entries);
+    }
+
+
+    // MS: the value on display goes into the slot
+    pub fn memory_store(&mut self, slot: &str) -> Result<(), CalcError> {
+        let value = self.finished_value()?;
+        self.set_memory(slot, value)
+    }
+
+
+    // M+ and M−: running totals, starting from zero in an empty slot
+    pub fn memory_add(&mut self, slot: &str) -> Result<(), CalcError> {
+        let value = self.finished_value()?;
+        let total = self.memory.get(slot).cloned().unwrap_or_else(|| Number::zero(self.number_mode)) + value;
+        self.set_memory(slot, total)
+    }
+
+
+    pub fn memory_subtract(&mut self, slot: &str) -> Result<(), CalcError> {
+        let value = self.finished_value()?;
+        let total = self.memory.get(slot).cloned().unwrap_or_else(|| Number::zero(self.number_mode)) - value;
+        self.set_memory(slot, total)
+    }
+
+
+    // MR: the stored value goes in wherever an operand can, with every digit it was stored with
+    pub fn memory_recall(&mut self, slot: &str) {
+        let Some(value) = self.memory.get(slot) else {
+            return;
+        };
+        let text = self.operand_text(value);
+
+        if self.should_reset_expression {
+            self.expression.clear();
+            self.should_reset_expression = false;
+        }
+
+        // Recalled values are closed: `3` `MR` is `3(0.5)`, and a digit after `MR` cannot make 150 into 1503
+        let text = if text.starts_with('(') { text } else { format!("({})", text) };
+
+        if self.can_start_operand() || self.can_multiply_implicitly() {
+            let tokens = self.tokens_of(&text);
+            self.expression.extend(tokens);
+            self.update_result();
+        }
+    }
+
+
+    pub fn memory_clear(&mut self, slot: &str) {
+        if self.memory.remove(slot).is_some() {
+            self.save_memory();
+        }
+    }
+
+
+    pub fn load_memory(&mut self) {
+        for (slot, value) in storage::load(MEMORY_FILE) {
+            match self.stored_value(&value) {
+                Ok(value) => {
+                    self.memory.insert(slot, value);
+                },
+                Err(e) => LOGGER.warn(&format!("Skipping stored memory slot {}: {}", slot, e)),
+            }
+        }
+    }
+
+
+    // MS, M+ and M− finish the expression like `=` and take its result, so the next number starts afresh
+    fn finished_value(&mut self) -> Result<Number, CalcError> {
+        if !self.should_reset_expression {
+            self.calculate();
+        }
+        self.shown_value.clone().ok_or_else(|| ErrorKind::NothingToStore.into())
+    }
+
+
+    fn set_memory(&mut self, slot: &str, value: Number) -> Result<(), CalcError> {
+        if !is_slot_name(slot) {
+            return Err(ErrorKind::InvalidSlotName(slot.to_string()).into());
+        }
+        if !value.is_finite() {
+            return Err(ErrorKind::ValueOutOfRange.into());
+        }
+
+        self.memory.insert(slot.to_string(), value);
+        self.save_memory();
+        Ok(())
+    }
+
+
+    // Untagged text from older files is evaluated in the current mode
+    fn stored_value(&self, text: &str) -> Result<Number, CalcError> {
+        match Number::from_stored(text) {
+            Some(value) => Ok(value),
+            None => self.evaluate_expression(text),
+        }
+    }
+
+
+    fn save_memory(&self) {
+        if !self.persistent {
+            return;
+        }
+
+        let entries: Vec<(String, String)> = self.memory
+            .iter()
+            .map(|(slot, value)| (slot.clone(), value.to_stored()))
+            .collect();
+        storage::save(MEMORY_FILE, &entries);
+    }
+
+
+    pub fn calculate(&mut self) {
+        if !self.text().is_empty() {
+            let text = self.balanced_expression();
+            self.expression = self.tokens_of(&text);
+            self.after_cursor.clear();
+            self.preview = Preview::Valid;
+            self.shown_value = None;
+            self.overflow = false;
+            self.shown_radix = None;
+            self.shown_entry = None;
+            let (target, value_expression) = split_assignment(&text);
+            let offset = text.len() - value_expression.len();
+            if let Some(signature) = target.filter(|t| t.contains(OPEN_PAREN)) {
+                let (signature, body) = (signature.to_string(), value_expression.to_string());
+                match self.define_function(&signature, &body) {
+                    Ok(()) => {
+                        LOGGER.info(&format!("Function defined: {}", signature));
+                        self.result = signature;
+                    },
+                    Err(e) => self.show_error(e, offset),
+                }
+                self.should_reset_expression = true;
+                self.settle();
+                return;
+            }
+
+            match self.evaluate_full(value_expression) {
+                Ok(Evaluation { value: result, overflow, radix }) => {
+                    if !result.is_finite() {
+                        self.show_error(ErrorKind::ValueOutOfRange.into(), offset);
+                    } else {
+                        if let Some(name) = target {
+                            self.variables.insert(name.to_string(), result.clone());
+                            self.save_variables();
+                            LOGGER.info(&format!("Variable set: {}", name));
+                            self.reparse_functions();
+                        }
+                        self.shown_radix = radix;
+                        self.result = self.format_shown(&result);
+                        self.shown_value = Some(result.clone());
+                        self.overflow = overflow;
+                        self.history.push(result.clone());
+                        self.shown_entry = Some(self.history.len());
+                        self.last_result = result;
+                    }
+                    self.should_reset_expression = true;
+                },
+                Err(e) => {
+                    self.show_error(e, offset);
+                    self.should_reset_expression = true;
+                }
+            }
+            self.settle();
+        }
+    }
+
+
+    fn show_error(&mut self, error: CalcError, offset: usize) {
+        LOGGER.warn(&format!("Calculation failed [{}]: {}", error.code(), error));
+        self.result = format!("Error: {}", error);
+        self.preview = Preview::Invalid(shift_span(error, offset));
+    }
+
+
+    // Numbers and names lose a character; operators, `sin(` and a bare `0x` go as a whole
+    pub fn backspace(&mut self) {
+        let whole = self.expression.last().is_some_and(Token::is_radix_prefix);
+        match self.expression.last_mut() {
+            Some(Token::Number(text) | Token::Name(text)) if !whole && text.chars().count() > 1 => {
+                text.pop();
+            },
+            Some(_) => {
+                self.expression.pop();
+            },
+            None => return,
+        }
+        self.update_result();
+    }
+
+
+    // What is just after the caret: the mirror image of `backspace`
+    pub fn delete(&mut self) {
+        let Some(first) = self.after_cursor.first_mut() else {
+            return;
+        };
+        let whole = first.is_radix_prefix();
+        let radix = first.radix().is_some();
+        match first {
+            Token::Number(digits) if radix && !whole => {
+                digits.drain(..2);
+            },
+            Token::Number(text) | Token::Name(text) if !whole && text.chars().count() > 1 => {
+                text.remove(0);
+            },
+            _ => {
+                self.after_cursor.remove(0);
+            },
+        }
+        // `0xFF` without its prefix is a name now
+        self.after_cursor = self.tokens_of(&to_text(&self.after_cursor));
+        self.update_result();
+    }
+
+
+    pub fn move_cursor_left(&mut self) {
+        let step = match self.expression.last() {
+            Some(token @ (Token::Number(text) | Token::Name(text))) if !token.is_radix_prefix() => {
+                text.chars().last().map_or(0, char::len_utf8)
+            },
+            Some(token) => token.to_string().len(),
+            None => 0,
+        };
+        let position = to_text(&self.expression).len() - step;
+        self.place_cursor(position);
+    }
+
+
+    pub fn move_cursor_right(&mut self) {
+        let step = match self.after_cursor.first() {
+            Some(token @ Token::Number(_)) if token.radix().is_some() => 2,
+            Some(Token::Number(text) | Token::Name(text)) => text.chars().next().map_or(0, char::len_utf8),
+            Some(token) => token.to_string().len(),
+            None => 0,
+        };
+        let position = to_text(&self.expression).len() + step;
+        self.place_cursor(position);
+    }
+
+
+    pub fn move_cursor_home(&mut self) {
+        self.place_cursor(0);
+    }
+
+
+    pub fn move_cursor_end(&mut self) {
+        let position = self.text().len();
+        self.place_cursor(position);
+    }
+
+
+    // Clicks count characters; a click inside `sin(`, `mod` or `0x` lands after it
+    pub fn set_cursor(&mut self, index: usize) {
+        let text = self.text();
+        let mut position = text.char_indices().nth(index).map_or(text.len(), |(i, _)| i);
+
+        let mut start = 0;
+        for token in self.tokens_of(&text) {
+            let end = start + token.to_string().len();
+            let inside = position > start && position < end;
+            if inside && token.radix().is_some() && position < start + 2 {
+                position = start + 2;
+            } else if inside && !matches!(token, Token::Number(_) | Token::Name(_)) {
+                position = end;
+            }
+            start = end;
+        }
+        self.place_cursor(position);
+    }
+
+
+    // Moving the caret means going back to editing, even right after `=`
+    fn place_cursor(&mut self, position: usize) {
+        let text = self.text();
+        self.expression = self.tokens_of(&text[..position]);
+        self.after_cursor = self.tokens_of(&text[position..]);
+        self.should_reset_expression = false;
+        self.settle();
+    }
+
+
+    pub fn clear(&mut self) {
+        self.expression.clear();
+        self.after_cursor.clear();
+        self.result = "0".to_string();
+        self.preview = Preview::Valid;
+        self.shown_value = None;
+        self.overflow = false;
+        self.shown_radix = None;
+        self.last_result = Number::zero(self.number_mode);
+        self.should_reset_expression = false;
+        self.settle();
+    }
+
+
+    // Goes back one edit; an `=` counts as one, and so does `clear`
+    pub fn undo(&mut self) {
+        if let Some(previous) = self.undo_stack.pop() {
+            let current = self.edit_state();
+            self.redo_stack.push(current);
+            self.restore(previous);
+        }
+    }
+
+
+    pub fn redo(&mut self) {
+        if let Some(next) = self.redo_stack.pop() {
+            let current = self.edit_state();
+            self.undo_stack.push(current);
+            self.restore(next);
+        }
+    }
+
+
+    pub fn get_expression(&self) -> String {
+        self.text()
+    }
+
+
+    // Characters before the caret
+    pub fn get_cursor(&self) -> usize {
+        to_text(&self.expression).chars().count()
+    }
+
+
+    pub fn get_result(&self) -> String {
+        self.result.clone()
+    }
+
+
+    pub fn get_preview(&self) -> &Preview {
+        &self.preview
+    }
+
+
+    // Span is relative to `get_expression()`
+    pub fn get_error(&self) -> Option<&CalcError> {
+        match &self.preview {
+            Preview::Invalid(error) => Some(error),
+            _ => None,
+        }
+    }
+
+
+    pub fn get_angle_mode(&self) -> String {
+        self.angle_mode.as_str().to_string()
+    }
+
+
+    pub fn get_number_mode(&self) -> String {
+        self.number_mode.as_str().to_string()
+    }
+
+
+    pub fn get_implicit_multiplication(&self) -> String {
+        self.implicit_multiplication.as_str().to_string()
+    }
+
+
+    // `#3` next to a fresh result, so it can be referred to later
+    pub fn get_entry_label(&self) -> String {
+        match self.shown_entry {
+            Some(entry) if self.should_reset_expression => format!("{}{}", HISTORY_REFERENCE, entry),
+            _ => String::new(),
+        }
+    }
+
+
+    pub fn get_auto_close_parentheses(&self) -> String {
+        if self.auto_close_parentheses { "On" } else { "Off" }.to_string()
+    }
+
+
+    // Brackets `=` would close, drawn after the expression while it is being typed
+    pub fn get_ghost_parentheses(&self) -> String {
+        if self.should_reset_expression || !self.auto_close_parentheses {
+            return String::new();
+        }
+        CLOSE_PAREN.to_string().repeat(self.open_parentheses())
+    }
+
+
+    pub fn get_programmer_mode(&self) -> bool {
+        self.programmer_mode
+    }
+
+
+    pub fn get_word_size(&self) -> String {
+        self.word_size.as_str().to_string()
+    }
+
+
+    pub fn get_signedness(&self) -> String {
+        self.signedness.as_str().to_string()
+    }
+
+
+    pub fn get_overflow(&self) -> bool {
+        self.programmer_mode && self.overflow
+    }
+
+
+    // The result in the usual bases and any asked for with `to base N`; the values are empty while
+    // there is no whole number to show
+    pub fn get_base_representations(&self) -> Vec<(String, String)> {
+        let value = self.shown_value.as_ref().and_then(Number::to_integer);
+        let extra = self.shown_radix
+            .filter(|radix| PANEL_BASES.iter().all(|&(_, base)| base != *radix))
+            .map(|radix| (format!("{} {}", BASE_WORD.to_uppercase(), radix), radix));
+        PANEL_BASES
+            .iter()
+            .map(|&(label, radix)| (label.to_string(), radix))
+            .chain(extra)
+            .map(|(label, radix)| {
+                let digits = value.clone().map(|value| programmer::to_radix_digits(&self.digits_of(value, radix), radix));
+                (label, digits.unwrap_or_default())
+            })
+            .collect()
+    }
+
+
+    // Register contents of the result in programmer mode, two's complement for negatives
+    pub fn get_word_pattern(&self) -> String {
+        match self.shown_value.as_ref().and_then(Number::to_integer) {
+            Some(value) if self.programmer_mode => programmer::format_word(&value, self.word_size),
+            _ => String::new(),
+        }
+    }
+
+
+    pub fn get_fraction_display(&self) -> String {
+        self.fraction_display.as_str().to_string()
+    }
+
+
+    pub fn get_complex_display(&self) -> String {
+        self.complex_display.as_str().to_string()
+    }
+
+
+    pub fn get_variables(&self) -> Vec<(String, String)> {
+        self.variables
+            .iter()
+            .map(|(name, value)| (name.clone(), self.format_result(value)))
+            .collect()
+    }
+
+
+    // Signature and body pairs for the settings menu
+    pub fn get_functions(&self) -> Vec<(String, String, String)> {
+        self.functions
+            .iter()
+            .map(|(name, function)| (name.clone(), format_signature(name, function), function.body.clone()))
+            .collect()
+    }
+
+
+    pub fn get_memory(&self) -> Vec<(String, String)> {
+        self.memory
+            .iter()
+            .map(|(slot, value)| (slot.clone(), self.format_result(value)))
+            .collect()
+    }
+
+
+    // Lights the M in the display
+    pub fn get_memory_indicator(&self) -> bool {
+        self.memory.contains_key(MEMORY_REGISTER)
+    }
+
+
+    // Re-renders a finished result after a display option changed
+    fn refresh_result(&mut self) {
+        if !self.should_reset_expression {
+            self.update_result();
+        } else if !self.result.contains("Error") {
+            // A finished result is reread from the register: 8-bit -1 is 255 once unsigned
+            if self.programmer_mode && let Some(value) = self.last_result.to_integer() {
+                let (wrapped, _) = programmer::wrap(&value, self.word_size, self.signedness);
+                self.last_result = Number::from_integer(wrapped);
+                self.shown_value = Some(self.last_result.clone());
+            }
+            self.result = self.format_shown(&self.last_result);
+        }
+    }
+
+
+    fn format_result(&self, num: &Number) -> String {
+        format_number(num, self.fraction_display, self.complex_display, self.angle_mode)
+    }
+
+
+    // In the base asked for with `in hex`, decimal otherwise
+    fn format_shown(&self, num: &Number) -> String {
+        match (self.shown_radix, num.to_integer()) {
+            (Some(radix), Some(value)) => programmer::format_radix(&self.digits_of(value, radix), radix),
+            _ => self.format_result(num),
+        }
+    }
+
+
+    // Programmer mode shows the register bits in the other bases, so -1 is `0xFF` in 8 bits
+    fn digits_of(&self, value: BigInt, radix: u32) -> BigInt {
+        if self.programmer_mode && radix != 10 {
+            programmer::to_word(&value, self.word_size)
+        } else {
+            value
+        }
+    }
+
+
+    // Continuing from a result carries on with its exact value, not the rounded display
+    fn result_as_expression(&self) -> String {
+        // An error message is no number to carry on from
+        if self.result.contains("Error") {
+            return String::new();
+        }
+        // `0xFF` and `1Z₃₆` are for reading; carrying on uses the plain number
+        self.operand_text(&self.last_result)
+    }
+
+
+    // A value as it is typed back in. The display form is kept when it reads back the same, so `0.5`
+    // stays `0.5`, while `1÷3` in decimals and `2^200` bring all their digits
+    fn operand_text(&self, value: &Number) -> String {
+        let mode = match value {
+            Number::Rational(value) if !value.is_integer() => {
+                return format!("({}÷{})", value.numer(), value.denom());
+            },
+            Number::Complex(value) => return format!("({})", format_complex_rectangular(*value)),
+            Number::Rational(_) => NumberMode::Exact,
+            Number::Decimal(_) => NumberMode::Precise,
+            Number::Float(_) => NumberMode::Fast,
+        };
+
+        let shown = self.format_result(value);
+        if Number::parse(&shown, mode).is_ok_and(|parsed| parsed == *value) {
+            shown
+        } else {
+            value.to_expression()
+        }
+    }
+
+
+    fn text(&self) -> String {
+        to_text(&self.expression) + &to_text(&self.after_cursor)
+    }
+
+
+    fn tokens_of(&self, text: &str) -> Vec<Token> {
+        tokenize(text, self.long_operators())
+    }
+
+
+    fn can_start_operand(&self) -> bool {
+        matches!(self.expression.last(),
+                 None | Some(Token::Operator(_) | Token::OpenParen | Token::Function(_) | Token::Separator | Token::Assign))
+    }
+
+
+    // A value just ended, so a bracket, function or name can follow as a product: `2(`, `3pi`,
+    // but not `x` + `pi`, which would merge into one name
+    fn can_multiply_implicitly(&self) -> bool {
+        match self.expression.last() {
+            Some(token @ Token::Number(digits)) => !digits.ends_with('.') && !token.is_radix_prefix(),
+            Some(Token::CloseParen | Token::Factorial | Token::Percent | Token::Degree) => true,
+            _ => false,
+        }
+    }
+
+
+    // Something `%`, `!` or `)` can follow: a number, a value or unit name (`pi`, `2rad`), a closed
+    // bracket or a suffix, but not a bare function name
+    fn ends_with_operand(&self) -> bool {
+        match self.expression.last() {
+            Some(token @ Token::Number(digits)) => !digits.ends_with('.') && !token.is_radix_prefix(),
+            Some(Token::Name(name)) => find_function(name).is_none(),
+            Some(Token::Answer | Token::CloseParen | Token::Percent | Token::Factorial | Token::Degree) => true,
+            _ => false,
+        }
+    }
+
+
+    // Whether the innermost open bracket belongs to a call: `f(3` but not `2×(3`
+    fn in_call_arguments(&self) -> bool {
+        let mut depth = 0;
+        for token in self.expression.iter().rev() {
+            match token {
+                Token::CloseParen => depth += 1,
+                Token::OpenParen | Token::Function(_) if depth == 0 => return matches!(token, Token::Function(_)),
+                Token::OpenParen | Token::Function(_) => depth -= 1,
+                _ => {}
+            }
+        }
+        false
+    }
+
+
+    fn trailing_operator(&self) -> Option<&str> {
+        match self.expression.last() {
+            Some(Token::Operator(operator)) => Some(operator),
+            _ => None,
+        }
+    }
+
+
+    // `mod`, `xor` and the like typed letter by letter become operators: `7mod`, `xxor`; in `0xffand`
+    // the `a` already went into the number as a hex digit and is taken back out
+    fn split_word_operator(&mut self) {
+        let Some(Token::Name(name)) = self.expression.last() else {
+            return;
+        };
+        let carried = match self.expression.iter().rev().nth(1) {
+            Some(token @ Token::Number(digits)) if token.radix().is_some() => &digits[2..],
+            _ => "",
+        };
+        let typed = format!("{}{}", carried, name);
+        let Some(word) = self.long_operators()
+            .iter()
+            .find(|operator| operator.starts_with(|c: char| c.is_ascii_alphabetic()) && typed.ends_with(*operator)) else {
+            return;
+        };
+
+        let rest = name[..name.len().saturating_sub(word.len())].to_string();
+        // Only after a whole operand: `theorem` and, in programmer mode, `floor` are names
+        if !rest.is_empty() && !self.is_value_name(&rest) {
+            return;
+        }
+        let taken = word.len().saturating_sub(name.len());
+        self.expression.pop();
+        if let Some(Token::Number(digits)) = self.expression.last_mut() && taken > 0 {
+            digits.truncate(digits.len() - taken);
+        }
+        if !rest.is_empty() {
+            self.expression.push(Token::Name(rest));
+        }
+        self.expression.push(Token::Operator(word.to_string()));
+    }
+
+
+    fn long_operators(&self) -> &'static [&'static str] {
+        if self.programmer_mode { PROGRAMMER_OPERATORS } else { LONG_OPERATORS }
+    }
+
+
+    // Programmer mode works on integers, which only the exact mode keeps whole at any size
+    fn literal_mode(&self) -> NumberMode {
+        if self.programmer_mode { NumberMode::Exact } else { self.number_mode }
+    }
+
+
+    fn open_parentheses(&self) -> usize {
+        self.expression.iter().chain(&self.after_cursor).fold(0, |open: usize, token| match token {
+            Token::OpenParen | Token::Function(_) => open + 1,
+            Token::CloseParen => open.saturating_sub(1),
+            _ => open,
+        })
+    }
+
+
+    // Still waiting for an operand: `2+`, `sin(`, `1.`, `x=`, `f(1,`, `#`
+    fn ends_incomplete(&self, text: &str) -> bool {
+        let Some(last_char) = text.chars().last() else {
+            return false;
+        };
+        self.ends_with_operator(text) ||
+            matches!(last_char, OPEN_PAREN | '.' | ASSIGN | ARGUMENT_SEPARATOR | HISTORY_REFERENCE) ||
+            self.ends_with_partial_conversion(text)
+    }
+
+
+    // An operator word counts where the parser splits it off: `7mod`, `xmod` for a variable `x`;
+    // `lorem` and `theorem` are names
+    fn ends_with_operator(&self, text: &str) -> bool {
+        let operator = trailing_operator(text, self.long_operators());
+        let name = trailing_name(text);
+        let head = &name[..name.len().saturating_sub(operator.len())];
+        !operator.is_empty() && (head.is_empty() || self.is_value_name(head)) && !self.is_known_name(name)
+    }
+
+
+    // `255 in hex` / `x to base 3` into the value expression and the base to show it in
+    fn split_conversion<'e>(&self, expr: &'e str) -> Result<(&'e str, Option<u32>), CalcError> {
+        let text = expr.trim_end();
+        let without_digits = text.trim_end_matches(|c: char| c.is_ascii_digit());
+        let digits = &text[without_digits.len()..];
+        let (head, base) = match without_digits.trim_end().strip_suffix(BASE_WORD) {
+            Some(head) if !digits.is_empty() => (head, digits),
+            _ => match RADIX_NAMES.iter().find(|name| text.ends_with(*name)) {
+                Some(name) => (&text[..text.len() - name.len()], *name),
+                None => return Ok((expr, None)),
+            },
+        };
+
+        let head = head.trim_end();
+        let Some(value) = CONVERSION_WORDS.iter().find_map(|word| head.strip_suffix(word)).filter(|value| self.stands_apart(value)) else {
+            return Ok((expr, None));
+        };
+        let radix = programmer::radix_of_name(base)
+            .or_else(|| base.parse().ok())
+            .filter(|radix| (programmer::MIN_RADIX..=programmer::MAX_RADIX).contains(radix));
+        match radix {
+            Some(radix) => Ok((value.trim_end(), Some(radix))),
+            None => Err(CalcError::at(ErrorKind::InvalidBase(base.to_string()), Span { start: value.len(), end: text.len() })),
+        }
+    }
+
+
+    // `2 in`, `2 to ba`, `2 to base1`: the base is still being typed
+    fn ends_with_partial_conversion(&self, text: &str) -> bool {
+        CONVERSION_WORDS.iter().any(|word| {
+            text.match_indices(word).any(|(index, _)| {
+                let rest: String = text[index + word.len()..].split_whitespace().collect();
+                self.stands_apart(&text[..index]) &&
+                    (matches!(rest.as_str(), BASE_WORD | "base1") ||
+                     RADIX_NAMES.iter().chain([&BASE_WORD]).any(|name| name.len() > rest.len() && name.starts_with(&rest)))
+            })
+        })
+    }
+
+
+    // `in`/`to` start a conversion after a value: a number, a bracket, or a name that is complete
+    // already, as in `xinhex` typed without spaces; not inside a name like `pin`
+    fn stands_apart(&self, before: &str) -> bool {
+        if before.trim().is_empty() {
+            return false;
+        }
+        if !before.ends_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+            return true;
+        }
+        let name = trailing_name(before);
+        name.is_empty() || self.is_value_name(name)
+    }
+
+
+    fn is_value_name(&self, name: &str) -> bool {
+        name == "i" || answer_index(name).is_some() || self.variables.contains_key(name) || find_constant(name).is_some()
+    }
+
+
+    fn is_known_name(&self, name: &str) -> bool {
+        self.is_value_name(name) || self.functions.contains_key(name) || find_function(name).is_some()
+    }
+
+
+    // Everything a name being typed can still turn into
+    fn completions(&self) -> impl Iterator<Item = &str> {
+        CONSTANTS.iter().map(|constant| constant.name)
+            .chain(FUNCTIONS.iter().map(|function| function.name))
+            .chain(self.variables.keys().map(String::as_str))
+            .chain(self.functions.keys().map(String::as_str))
+            .chain(["ans", "if", "rad", "grad"])
+            .chain(self.long_operators().iter().copied())
+            .chain(CONVERSION_WORDS.iter().copied())
+    }
+
+
+    // `p` on the way to `pi`, `sq` to `sqrt`, `xmo` to `x mod`: not wrong while the caret is still on it
+    fn is_name_being_typed(&self, error: &CalcError) -> bool {
+        let (ErrorKind::UnknownVariable(name) | ErrorKind::UnknownFunction(name)) = &error.kind else {
+            return false;
+        };
+        to_text(&self.expression).ends_with(name.as_str()) &&
+            (0..name.len()).any(|split| {
+                let (head, rest) = name.split_at(split);
+                (head.is_empty() || self.is_value_name(head)) &&
+                    self.completions().any(|known| known.len() > rest.len() && known.starts_with(rest))
+            })
+    }
+
+
+    // `sin(30` reads as `sin(30)` while brackets are closed automatically
+    fn balanced_expression(&self) -> String {
+        let mut expression = self.text();
+        if self.auto_close_parentheses && !self.ends_incomplete(&expression) {
+            // `sqrt(16 in hex` closes before the conversion
+            let end = self.split_conversion(&expression).map_or(expression.len(), |(value, _)| value.len());
+            expression.insert_str(end, &CLOSE_PAREN.to_string().repeat(self.open_parentheses()));
+        }
+        expression
+    }
+
+
+    fn update_result(&mut self) {
+        self.update_preview();
+        self.settle();
+    }
+
+
+    fn update_preview(&mut self) {
+        if self.text().is_empty() {
+            self.result = "0".to_string();
+            self.preview = Preview::Valid;
+            self.shown_value = None;
+            self.overflow = false;
+            self.shown_radix = None;
+            return;
+        }
+
+        let expression = self.balanced_expression();
+        let (target, value_expression) = split_assignment(&expression);
+        let offset = expression.len() - value_expression.len();
+
+        // A definition has no value yet; its body only has to read with the parameters in scope
+        if let Some(signature) = target.filter(|t| t.contains(OPEN_PAREN)) {
+            let definition = self.parse_definition(signature, value_expression);
+            self.result = "0".to_string();
+            self.shown_value = None;
+            self.overflow = false;
+            self.shown_radix = None;
+            self.preview = match definition {
+                Ok(_) => Preview::Valid,
+                Err(e) if e.kind.is_incomplete() || e.kind == ErrorKind::EmptyFunctionBody => Preview::Incomplete,
+                Err(e) => Preview::Invalid(shift_span(e, offset)),
+            };
+            return;
+        }
+
+        match self.evaluate_full(value_expression) {
+            Ok(Evaluation { value: result, overflow, radix }) => {
+                if !result.is_finite() {
+                    self.result = "0".to_string();
+                    self.preview = Preview::Invalid(ErrorKind::ValueOutOfRange.into());
+                    self.shown_value = None;
+                    self.overflow = false;
+                } else {
+                    self.shown_radix = radix;
+                    self.result = self.format_shown(&result);
+                    self.preview = Preview::Valid;
+                    self.shown_value = Some(result);
+                    self.overflow = overflow;
+                }
+            },
+            // Keeps showing the value of the part typed so far, unless that was already wrong
+            Err(e) if e.kind.is_incomplete() || self.is_name_being_typed(&e) => {
+                if matches!(self.preview, Preview::Invalid(_)) {
+                    self.result = "0".to_string();
+                    self.shown_value = None;
+                }
+                self.preview = Preview::Incomplete;
+            },
+            Err(e) => {
+                self.result = "0".to_string();
+                self.preview = Preview::Invalid(shift_span(e, offset));
+                self.shown_value = None;
+                self.overflow = false;
+                self.shown_radix = None;
+            }
+        }
+    }
+
+
+    fn edit_state(&self) -> EditState {
+        EditState {
+            expression: self.expression.clone(),
+            after_cursor: self.after_cursor.clone(),
+            result: self.result.clone(),
+            preview: self.preview.clone(),
+            shown_value: self.shown_value.clone(),
+            overflow: self.overflow,
+            shown_radix: self.shown_radix,
+            shown_entry: self.shown_entry,
+            last_result: self.last_result.clone(),
+            should_reset_expression: self.should_reset_expression,
+        }
+    }
+
+
+    fn restore(&mut self, state: EditState) {
+        self.expression = state.expression.clone();
+        self.after_cursor = state.after_cursor.clone();
+        self.result = state.result.clone();
+        self.preview = state.preview.clone();
+        self.shown_value = state.shown_value.clone();
+        self.overflow = state.overflow;
+        self.shown_radix = state.shown_radix;
+        self.shown_entry = state.shown_entry;
+        self.last_result = state.last_result.clone();
+        self.should_reset_expression = state.should_reset_expression;
+        self.settled = Some(state);
+    }
+
+
+    // Called after every change; only a different expression, or an `=` and the first key after it,
+    // makes an undo step, so caret moves and mode toggles just refresh the state to come back to
+    fn settle(&mut self) {
+        let current = self.edit_state();
+        let Some(previous) = self.settled.replace(current) else {
+            return;
+        };
+        let changed = to_text(&previous.expression) + &to_text(&previous.after_cursor) != self.text() ||
+            previous.should_reset_expression != self.should_reset_expression;
+        if changed {
+            self.undo_stack.push(previous);
+            if self.undo_stack.len() > MAX_UNDO_STEPS {
+                self.undo_stack.remove(0);
+            }
+            self.redo_stack.clear();
+        }
+    }
+
+
+    fn evaluate_expression(&self, expr: &str) -> Result<Number, CalcError> {
+        self.evaluate_full(expr).map(|evaluation| evaluation.value)
+    }
+
+
+    fn evaluate_full(&self, expr: &str) -> Result<Evaluation, CalcError> {
+        if expr.is_empty() {
+            return Ok(Evaluation { value: Number::zero(self.number_mode), overflow: false, radix: None });
+        }
+        
+        if self.ends_incomplete(expr) {
+            let operator_start = expr.len() - trailing_operator(expr, self.long_operators()).len().max(1);
+            return Err(CalcError::at(ErrorKind::IncompleteExpression, Span { start: operator_start, end: expr.len() }));
+        }
+        
+        let (expr, radix) = self.split_conversion(expr)?;
+        let tree = self.parse(expr, &[])?;
+        let (value, overflow) = self.on_evaluation_stack(|| {
+            let evaluator = Evaluator::new(self);
+            (evaluator.evaluate(&tree), evaluator.overflow.get())
+        });
+        let value = value?;
+        // Only whole numbers have digits in another base
+        if radix.is_some_and(|radix| radix != 10) && value.to_integer().is_none() {
+            return Err(CalcError::at(ErrorKind::NotAnInteger(value.to_expression()), tree.span));
+        }
+        Ok(Evaluation { value, overflow, radix })
+    }
+
+
+    // Recursive definitions run deep; the UI thread's stack is too small to trust with them
+    fn on_evaluation_stack<T: Send>(&self, work: impl FnOnce() -> T + Send) -> T {
+        thread::scope(|scope| {
+            let evaluation = thread::Builder::new()
+                .stack_size(EVALUATION_STACK_SIZE)
+                .spawn_scoped(scope, work)
+                .expect("Cant start the evaluation thread");
+            evaluation.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+        })
+    }
+
+
+    // `params` are the names a function body may use besides the calculator's own
+    fn parse(&self, text: &str, params: &[String]) -> Result<Expr, CalcError> {
+        let lookup = |name: &str| {
+            if params.iter().any(|p| p == name) || self.variables.contains_key(name) {
+                Some(NameKind::Value)
+            } else if self.functions.contains_key(name) {
+                Some(NameKind::Function)
+            } else {
+                None
+            }
+        };
+        Parser::new(text, self.implicit_multiplication, self.programmer_mode, &lookup).parse()
+    }
+}
+
+
+
+// Walks a parsed expression; a user function body gets its own evaluator with the arguments as locals
+struct Evaluator<'a> {
+    calculator: &'a Calculator,
+    locals: BTreeMap<String, Number>,
+    depth: usize,
+    // Nodes being evaluated, counted through function calls too
+    nesting: Cell<usize>,
+    // Set once any step has to be wrapped to the word size
+    overflow: Cell<bool>,
+}
+
+
+
+impl<'a> Evaluator<'a> {
+    fn new(calculator: &'a Calculator) -> Self {
+        Self {
+            calculator,
+            locals: BTreeMap::new(),
+            depth: 0,
+            nesting: Cell::new(0),
+            overflow: Cell::new(false),
+        }
+    }
+
+
+    fn evaluate(&self, tree: &Expr) -> Result<Number, CalcError> {
+        let nesting = self.nesting.get();
+        if nesting >= MAX_NESTING_DEPTH {
+            return Err(CalcError::at(ErrorKind::NestingTooDeep(MAX_NESTING_DEPTH), tree.span));
+        }
+
+        self.nesting.set(nesting + 1);
+        let value = self.evaluate_node(tree)
+            .and_then(|value| self.fit_word(value))
+            .map_err(|e| e.or_span(tree.span));
+        self.nesting.set(nesting);
+        value
+    }
+
+
+    // In programmer mode every step is a register: it has to stay whole, so `7/2` is caught where it
+    // happens, and it wraps around at the word size like the hardware would
+    fn fit_word(&self, value: Number) -> Result<Number, CalcError> {
+        if !self.calculator.programmer_mode {
+            return Ok(value);
+        }
+
+        let (word, overflowed) = programmer::wrap(&integer(&value)?, self.calculator.word_size, self.calculator.signedness);
+        if overflowed {
+            self.overflow.set(true);
+        }
+        Ok(Number::from_integer(word))
+    }
+
+
+    // Bitwise results are patterns, not sums, so bits falling off the word are not an overflow
+    fn bits(&self, value: BigInt) -> Number {
+        Number::from_integer(programmer::wrap(&value, self.calculator.word_size, self.calculator.signedness).0)
+    }
+
+
+    fn evaluate_node(&self, tree: &Expr) -> Result<Number, CalcError> {
+        match &tree.kind {
+            ExprKind::Number(literal) => Number::parse(literal, self.calculator.literal_mode()),
+            ExprKind::Name(name) => self.lookup(name),
+            ExprKind::Negate(operand) => match &operand.kind {
+                // `-128` is one literal to an 8-bit register, not 128 negated
+                ExprKind::Number(literal) => Ok(-Number::parse(literal, self.calculator.literal_mode())?),
+                _ => Ok(-self.evaluate(operand)?),
+            },
+            ExprKind::BitNot(operand) => Ok(self.bits(programmer::not(&integer(&self.evaluate(operand)?)?))),
+            ExprKind::Binary(op, lhs, rhs) => {
+                let lhs = self.evaluate(lhs)?;
+                let rhs = self.evaluate(rhs)?;
+                match op {
+                    BinaryOp::Add => Ok(lhs + rhs),
+                    BinaryOp::Sub => Ok(lhs - rhs),
+                    BinaryOp::Mul => Ok(lhs * rhs),
+                    BinaryOp::Div => lhs.checked_div(rhs),
+                    BinaryOp::Pow => lhs.pow(rhs),
+                    BinaryOp::Mod => lhs.modulo(rhs),
+                    BinaryOp::Rem => lhs.remainder(rhs),
+                    BinaryOp::FloorDiv => lhs.floor_div(rhs),
+                    BinaryOp::PercentAdd => Ok(lhs.clone() + lhs * rhs),
+                    BinaryOp::PercentSub => Ok(lhs.clone() - lhs * rhs),
+                    BinaryOp::Bit(op) => {
+                        programmer::apply(*op, &integer(&lhs)?, &integer(&rhs)?, self.calculator.word_size).map(|value| self.bits(value))
+                    },
+                }
+            },
+            ExprKind::Postfix(op, operand) => {
+                let value = self.evaluate(operand)?;
+                match op {
+                    PostfixOp::Factorial => factorial(&value),
+                    PostfixOp::Percent => value.checked_div(Number::parse("100", self.calculator.literal_mode())?),
+                    PostfixOp::Imaginary => Ok(value * Number::imaginary_unit()),
+                    // Converted into the current mode, so `sin(30°)` works in any mode
+                    PostfixOp::Angle(unit) => Ok(value.map_f64(|v| unit.convert(v, self.calculator.angle_mode))),
+                }
+            },
+            ExprKind::Call(name, arguments) => self.call(name, arguments),
+            ExprKind::Answer(back) => {
+                let reference = if *back == 1 { "ans".to_string() } else { format!("ans{}", back) };
+                let index = self.calculator.history.len().checked_sub(*back);
+                self.history_entry(index, reference)
+            },
+            ExprKind::Entry(entry) => self.history_entry(entry.checked_sub(1), format!("{}{}", HISTORY_REFERENCE, entry)),
+        }
+    }
+
+
+    fn history_entry(&self, index: Option<usize>, reference: String) -> Result<Number, CalcError> {
+        index
+            .and_then(|index| self.calculator.history.get(index))
+            .cloned()
+            .ok_or_else(|| ErrorKind::MissingHistoryEntry(reference).into())
+    }
+
+
+    fn lookup(&self, name: &str) -> Result<Number, CalcError> {
+        if name == "i" {
+            return Ok(Number::imaginary_unit());
+        }
+        if let Some(value) = self.locals.get(name).or_else(|| self.calculator.variables.get(name)) {
+            return Ok(value.clone());
+        }
+        if let Some(constant) = find_constant(name) {
+            // Fractions only for whole constants: π is irrational, and `h` or `G` are no 42-digit denominators
+            let mode = match self.calculator.literal_mode() {
+                NumberMode::Exact if constant.value.contains(['.', 'e']) => NumberMode::Precise,
+                mode => mode,
+            };
+            return Number::parse(constant.value, mode);
+        }
+        if self.calculator.functions.contains_key(name) || find_function(name).is_some() {
+            return Err(ErrorKind::MissingArguments(name.to_string()).into());
+        }
+        Err(ErrorKind::UnknownVariable(name.to_string()).into())
+    }
+
+
+    fn call(&self, name: &str, arguments: &[Expr]) -> Result<Number, CalcError> {
+        if name == "if" {
+            return self.evaluate_condition(arguments);
+        }
+        if let Some(function) = self.calculator.functions.get(name) {
+            let values = arguments.iter().map(|argument| self.evaluate(argument)).collect::<Result<Vec<_>, _>>()?;
+            return self.call_user_function(name, function, values);
+        }
+
+        let function = find_function(name).ok_or_else(|| ErrorKind::UnknownFunction(name.to_string()))?;
+        let [argument] = arguments else {
+            return Err(ErrorKind::ArgumentCount { name: name.to_string(), expected: 1, found: arguments.len() }.into());
+        };
+        function.evaluate(&self.evaluate(argument)?, self.calculator.angle_mode)
+    }
+
+
+    fn call_user_function(&self, name: &str, function: &UserFunction, arguments: Vec<Number>) -> Result<Number, CalcError> {
+        if arguments.len() != function.params.len() {
+            let (expected, found) = (function.params.len(), arguments.len());
+            return Err(ErrorKind::ArgumentCount { name: name.to_string(), expected, found }.into());
+        }
+        if self.depth >= MAX_CALL_DEPTH {
+            return Err(ErrorKind::RecursionLimit { name: name.to_string(), limit: MAX_CALL_DEPTH }.into());
+        }
+
+        let evaluator = Evaluator {
+            calculator: self.calculator,
+            locals: function.params.iter().cloned().zip(arguments).collect(),
+            depth: self.depth + 1,
+            nesting: Cell::new(self.nesting.get()),
+            overflow: Cell::new(false),
+        };
+        // Spans inside the body refer to the function's own text, so the call site takes the blame
+        let value = evaluator.evaluate(&function.tree).map_err(|e| CalcError { span: None, ..e });
+        if evaluator.overflow.get() {
+            self.overflow.set(true);
+        }
+        value
+    }
+
+
+    // `if(n, n×f(n-1), 1)`: only the chosen branch is evaluated, which is what lets recursion stop
+    fn evaluate_condition(&self, arguments: &[Expr]) -> Result<Number, CalcError> {
+        let [condition, then, otherwise] = arguments else {
+            return Err(ErrorKind::ArgumentCount { name: "if".to_string(), expected: 3, found: arguments.len() }.into());
+        };
+
+        if !self.evaluate(condition)?.is_zero() {
+            self.evaluate(then)
+        } else {
+            self.evaluate(otherwise)
+        }
+    }
+}
+
+
+
+// Errors come back relative to the text after `x=`; the display needs them relative to the whole expression
+fn shift_span(error: CalcError, offset: usize) -> CalcError {
+    let span = error.span.map(|span| Span { start: span.start + offset, end: span.end + offset });
+    CalcError { span, ..error }
+}
+
+
+
+fn integer(value: &Number) -> Result<BigInt, CalcError> {
+    value.to_integer().ok_or_else(|| ErrorKind::NotAnInteger(value.to_expression()).into())
+}
+
+
+
+// `x=12.5` / `rate:=0.07` / `f(x,y)=x^2+y` into the target and the value expression
+fn split_assignment(expr: &str) -> (Option<&str>, &str) {
+    if let Some((target, value)) = expr.split_once(ASSIGN) {
+        let target = target.trim().trim_end_matches(':').trim_end();
+        if (is_variable_name(target) || parse_signature(target).is_some()) && !value.trim().is_empty() {
+            return (Some(target), value);
+        }
+    }
+    (None, expr)
+}
+
+
+
+// Slots have names of their own, apart from variables: `total`, `tax_2024`
+fn is_slot_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic()) && name.chars().all(is_identifier_char)
+}
+
+
+
+fn is_variable_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name.chars().all(is_identifier_char)
+        && !RESERVED_NAMES.contains(&name)
+        && answer_index(name).is_none()
+        && find_function(name).is_none()
+}
+
+
+
+// The identifier `text` ends with, without the digits of a product like `2x`; none after `0xff`
+fn trailing_name(text: &str) -> &str {
+    let run = &text[text.trim_end_matches(is_identifier_char).len()..];
+    if Token::Number(run.to_string()).radix().is_some() {
+        return "";
+    }
+    run.trim_start_matches(|c: char| c.is_ascii_digit())
+}
+
+
+
+// `×`, `mod`, `//` at the end of `text`, or nothing; a single `%` is a percent, not an operator
+fn trailing_operator<'t>(text: &'t str, operators: &[&str]) -> &'t str {
+    if let Some(operator) = operators.iter().find(|operator| text.ends_with(*operator)) {
+        return &text[text.len() - operator.len()..];
+    }
+    match text.chars().last() {
+        Some(c) if OPERATORS.contains(c) => &text[text.len() - c.len_utf8()..],
+        _ => "",
+    }
+}
+
+
+
+// `f(x, y)` into the function name and its parameter names
+fn parse_signature(signature: &str) -> Option<(String, Vec<String>)> {
+    let signature = signature.replace(' ', "");
+    let (name, rest) = signature.split_once(OPEN_PAREN)?;
+    let params = rest.strip_suffix(CLOSE_PAREN)?;
+    let params: Vec<String> = if params.is_empty() {
+        Vec::new()
+    } else {
+        params.split(ARGUMENT_SEPARATOR).map(str::to_string).collect()
+    };
+
+    let distinct = params.iter().enumerate().all(|(index, param)| !params[..index].contains(param));
+    if is_variable_name(name) && params.iter().all(|param| is_variable_name(param)) && distinct {
+        Some((name.to_string(), params))
+    } else {
+        None
+    }
+}
+
+
+
+fn format_signature(name: &str, function: &UserFunction) -> String {
+    format!("{}({})", name, function.params.join(","))
+}
+
+
+
+fn format_number(num: &Number, fraction_display: FractionDisplay, complex_display: ComplexDisplay, angle_mode: AngleMode) -> String {
+    match num {
+        Number::Rational(value) => format_rational(value, fraction_display),
+        Number::Decimal(value) => format_decimal(value),
+        Number::Float(value) => format_float(*value),
+        Number::Complex(value) => match complex_display {
+            ComplexDisplay::Rectangular => format_complex_rectangular(*value),
+            ComplexDisplay::Polar => format_complex_polar(*value, angle_mode),
+        },
+    }
+}
+
+
+
+// Rounding noise like the 1e-16 in `exp(pi*i)` is dropped relative to the modulus
+fn format_complex_rectangular(num: Complex64) -> String {
+    let noise = num.norm() * COMPLEX_NOISE;
+    let re = if num.re.abs() < noise { 0.0 } else { num.re };
+    let im = if num.im.abs() < noise { 0.0 } else { num.im };
+
+    let imaginary = match im {
+        1.0 => "i".to_string(),
+        -1.0 => "-i".to_string(),
+        _ => format!("{}i", format_float(im)),
+    };
+
+    if im == 0.0 {
+        format_float(re)
+    } else if re == 0.0 {
+        imaginary
+    } else if im > 0.0 {
+        format!("{}+{}", format_float(re), imaginary)
+    } else {
+        format!("{}{}", format_float(re), imaginary)
+    }
+}
+
+
+
+fn format_complex_polar(num: Complex64, angle_mode: AngleMode) -> String {
+    let (modulus, argument) = num.to_polar();
+    let argument = AngleMode::Radians.convert(argument, angle_mode);
+    let unit = match angle_mode {
+        AngleMode::Degrees => "°",
+        AngleMode::Radians => "",
+        AngleMode::Gradians => "grad",
+    };
+    format!("{}∠{}{}", format_float(modulus), format_float(argument), unit)
+}
+
+
+
+fn format_rational(num: &BigRational, fraction_display: FractionDisplay) -> String {
+    if num.is_integer() || fraction_display == FractionDisplay::Decimal {
+        return format_decimal(&rational_to_decimal(num));
+    }
+
+    let sign = if num.is_negative() { "-" } else { "" };
+    let (whole, fraction) = split_mixed(num);
+    if fraction_display == FractionDisplay::Mixed && !whole.is_zero() {
+        format!("{}{} {}/{}", sign, whole, fraction.numer(), fraction.denom())
+    } else {
+        format!("{}", num)
+    }
+}
+
+
+
+fn format_float(num: f64) -> String {
+    if num.is_nan() {
+        return "Error".to_string();
+    }
+    if num.is_infinite() {
+        return if num.is_sign_positive() { "Infinity" } else { "-Infinity" }.to_string();
+    }
+
+    if num == 0.0 {
+        return "0".to_string();
+    }
+    // Near-whole noise is dropped, but `6.62607015e-34` is a value, not noise around 0
+    if num.fract().abs() < 1e-10 && (1.0..1e15).contains(&num.abs()) {
+        return format!("{}", num as i64);
+    }
+
+    let formatted = format!("{:.8}", num);
+    let trimmed = formatted.trim_end_matches('0').trim_end_matches('.');
+    if trimmed.len() > 12 || num.abs() > 1e15 || num.abs() < 1e-4 {
+        let scientific = format!("{:.6e}", num);
+        let (mantissa, exponent) = scientific.split_once('e').unwrap_or((&scientific, "0"));
+        format!("{}e{}", mantissa.trim_end_matches('0').trim_end_matches('.'), exponent)
+    } else {
+        trimmed.to_string()
+    }
+}
+
+
+
+// Integers are shown digit for digit, fractions are rounded only for display
+fn format_decimal(num: &BigDecimal) -> String {
+    if num.is_zero() {
+        return "0".to_string();
+    }
+
+    let (_, scale) = num.as_bigint_and_exponent();
+    let magnitude = num.digits() as i64 - scale - 1;
+    if !(-4..MAX_PLAIN_DIGITS).contains(&magnitude) {
+        return num.with_prec(SCIENTIFIC_DIGITS).normalized().to_scientific_notation();
+    }
+
+    num.round(DECIMAL_DISPLAY_PLACES).normalized().to_plain_string()
+}
+
+
+
+pub fn handle_calculator_input(button_id: &str, calculator: &mut Calculator) {
+    match button_id {
+        "0" | "1" | "2" | "3" | "4" | "5" | "6" | "7" | "8" | "9" => {
+            calculator.add_digit(button_id);
         },
         "decimal" => {
             calculator.add_decimal();
         },
-        "plus" | "minus" | "multiply" | "divide" => {
+        "plus" | "minus" | "multiply" | "divide" | "slash" | "power" | "mod" | "rem" | "floor-divide" |
+        "and" | "or" | "xor" | "shift-left" | "shift-right" | "rol" | "ror" => {
             calculator.add_operator(button_id);
         },
+        "not" => {
+            calculator.add_not();
+        },
+        "0x" | "0b" | "0o" => {
+            calculator.add_literal_prefix(button_id);
+        },
+        "square" => {
+            calculator.add_square();
+        },
+        "percent" => {
+            calculator.add_percent();
+        },
+        "factorial" => {
+            calculator.add_factorial();
+        },
         "open-paren" | "close-paren" => {
             calculator.add_parenthesis(button_id);
         },
         "equals" => {
             calculator.calculate();
         },
+        "equals-sign" => {
+            calculator.add_equals_sign();
+        },
         "backspace" => {
             calculator.backspace();
         },
+        "delete" => {
+            calculator.delete();
+        },
         "clear" => {
             calculator.clear();
         },
+        "undo" => {
+            calculator.undo();
+        },
+        "redo" => {
+            calculator.redo();
+        },
+        "cursor-left" => {
+            calculator.move_cursor_left();
+        },
+        "cursor-right" => {
+            calculator.move_cursor_right();
+        },
+        "cursor-home" => {
+            calculator.move_cursor_home();
+        },
+        "cursor-end" => {
+            calculator.move_cursor_end();
+        },
+        key if key.starts_with(MEMORY_KEY_PREFIX) => {
+            handle_memory_key(key, calculator);
+        },
+        name if find_function(name).is_some() => {
+            calculator.add_function(name);
+        },
+        letter if letter.len() == 1 && (letter.as_bytes()[0].is_ascii_alphabetic() || letter == "_") => {
+            calculator.add_letter(letter.as_bytes()[0] as char);
+        },
+        "degree" => {
+            calculator.add_degree_sign();
+        },
+        "angle-mode" => {
+            calculator.toggle_angle_mode();
+        },
+        "number-mode" => {
+            calculator.toggle_number_mode();
+        },
+        "implicit-multiplication" => {
+            calculator.toggle_implicit_multiplication();
+        },
+        "auto-close-parentheses" => {
+            calculator.toggle_auto_close_parentheses();
+        },
+        "programmer-mode" => {
+            calculator.toggle_programmer_mode();
+        },
+        "word-size" => {
+            calculator.toggle_word_size();
+        },
+        "signedness" => {
+            calculator.toggle_signedness();
+        },
+        "fraction-display" => {
+            calculator.toggle_fraction_display();
+        },
+        "complex-display" => {
+            calculator.toggle_complex_display();
+        },
+        "assign" => {
+            calculator.add_assignment();
+        },
+        "separator" => {
+            calculator.add_separator();
+        },
+        "ans" => {
+            calculator.add_answer();
+        },
+        "history-reference" => {
+            calculator.add_history_reference();
+        },
         "settings" => {
             LOGGER.info("Settings button pressed");
         },
+        "variables" => {
+            LOGGER.info("Variables button pressed");
+        },
+        "constants" => {
+            LOGGER.info("Constants button pressed");
+        },
+        "memory" => {
+            LOGGER.info("Memory button pressed");
+        },
         _ => {}
     }
+}
+
+
+
+// `memory-add` works on the M register, `memory-add:total` on a named slot
+fn handle_memory_key(button_id: &str, calculator: &mut Calculator) {
+    let (key, slot) = button_id.split_once(':').unwrap_or((button_id, MEMORY_REGISTER));
+    let slot = slot.trim();
+    let outcome = match key {
+        "memory-store" => calculator.memory_store(slot),
+        "memory-add" => calculator.memory_add(slot),
+        "memory-subtract" => calculator.memory_subtract(slot),
+        "memory-recall" => {
+            calculator.memory_recall(slot);
+            Ok(())
+        },
+        "memory-clear" => {
+            calculator.memory_clear(slot);
+            Ok(())
+        },
+        _ => Ok(()),
+    };
+    if let Err(e) = outcome {
+        LOGGER.warn(&format!("Memory slot {} not changed: {}", slot, e));
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    // Nothing a test defines may reach the developer's own data files
+    fn calculator() -> Calculator {
+        Calculator { persistent: false, ..Calculator::new() }
+    }
+
+
+    fn press(keys: &[&str]) -> Calculator {
+        let mut calculator = calculator();
+        for key in keys {
+            handle_calculator_input(key, &mut calculator);
+        }
+        calculator
+    }
+
+
+    fn error_code(calculator: &Calculator) -> Option<&'static str> {
+        calculator.get_error().map(CalcError::code)
+    }
+
+
+    #[test]
+    fn powers_are_right_associative() {
+        assert_eq!(press(&["2", "power", "3", "power", "2", "equals"]).get_result(), "512");
+        assert_eq!(press(&["minus", "2", "power", "2", "equals"]).get_result(), "-4");
+    }
+
+
+    #[test]
+    fn exact_mode_shows_fractions() {
+        let calculator = press(&["number-mode", "1", "divide", "3", "plus", "1", "divide", "6", "equals"]);
+        assert_eq!(calculator.get_result(), "1/2");
+    }
+
+
+    #[test]
+    fn square_roots_of_negatives_are_imaginary() {
+        assert_eq!(press(&["sqrt", "minus", "4", "close-paren", "equals"]).get_result(), "2i");
+        assert_eq!(press(&["i", "power", "2", "equals"]).get_result(), "-1");
+    }
+
+
+    #[test]
+    fn percent_after_plus_or_minus_is_a_share_of_the_left_side() {
+        assert_eq!(press(&["2", "0", "0", "plus", "1", "0", "percent", "equals"]).get_result(), "220");
+        assert_eq!(press(&["2", "0", "0", "minus", "1", "0", "percent", "equals"]).get_result(), "180");
+        assert_eq!(press(&["5", "0", "multiply", "1", "0", "percent", "equals"]).get_result(), "5");
+    }
+
+
+    #[test]
+    fn runaway_recursion_is_an_error() {
+        let mut calculator = calculator();
+        calculator.define_function("g(n)", "if(n,1+g(n-1),0)").unwrap();
+        calculator.define_function("h(n)", "if(n,1+abs(abs(abs(abs(abs(abs(abs(abs(h(n-1))))))))),0)").unwrap();
+
+        assert_eq!(calculator.evaluate_expression("g(100)").unwrap().to_expression(), "100");
+        assert_eq!(calculator.evaluate_expression("g(10000)").unwrap_err().code(), "recursion-limit");
+        assert_eq!(calculator.evaluate_expression("h(10000)").unwrap_err().code(), "nesting-too-deep");
+    }
+
+
+    #[test]
+    fn continuing_from_a_result_keeps_its_precision() {
+        let calculator = press(&["1", "divide", "3", "equals", "multiply", "3", "equals"]);
+        assert_eq!(calculator.get_result(), "1");
+
+        let calculator = press(&["2", "power", "2", "0", "0", "equals", "plus"]);
+        assert_eq!(calculator.get_expression(), "1606938044258990275541962092341162602522202993782792835301376+");
+
+        let calculator = press(&["2", "power", "2", "0", "0", "equals", "minus", "2", "power", "2", "0", "0", "equals"]);
+        assert_eq!(calculator.get_result(), "0");
+
+        let calculator = press(&["1", "decimal", "5", "equals", "plus"]);
+        assert_eq!(calculator.get_expression(), "1.5+");
+    }
+
+
+    #[test]
+    fn functions_keep_decimal_precision() {
+        let result = |keys: &[&str]| press(keys).get_result();
+        assert_eq!(result(&["sqrt", "2", "close-paren", "power", "2", "equals"]), "2");
+        assert_eq!(result(&["2", "power", "0", "decimal", "5", "multiply", "2", "power", "0", "decimal", "5", "equals"]), "2");
+        assert_eq!(result(&["abs", "minus", "1", "2", "3", "4", "5", "6", "7", "8", "9", "0", "1", "2", "3", "4", "5", "6", "7", "8",
+                            "9", "0", "1", "close-paren", "equals"]), "123456789012345678901");
+        assert_eq!(result(&["floor", "1", "2", "3", "4", "5", "6", "7", "8", "9", "0", "1", "2", "3", "4", "5", "6", "7", "8",
+                            "decimal", "5", "close-paren", "equals"]), "123456789012345678");
+        assert_eq!(result(&["angle-mode", "angle-mode", "sin", "1", "8", "0", "close-paren", "equals"]), "0");
+        assert_eq!(result(&["angle-mode", "angle-mode", "sin", "3", "0", "close-paren", "equals"]), "0.5");
+        assert_eq!(result(&["number-mode", "sqrt", "9", "divide", "4", "close-paren", "equals"]), "3/2");
+    }
+
+
+    #[test]
+    fn constants_keep_their_kind_of_value() {
+        let result = |keys: &[&str]| press(keys).get_result();
+        assert_eq!(result(&["number-mode", "p", "i", "equals"]), "3.1415926535897932");
+        assert_eq!(result(&["number-mode", "h", "equals"]), "6.62607015e-34");
+        assert_eq!(result(&["number-mode", "c", "equals"]), "299792458");
+        assert_eq!(result(&["number-mode", "number-mode", "h", "equals"]), "6.62607e-34");
+        assert_eq!(result(&["number-mode", "number-mode", "k", "_", "B", "equals"]), "1.380649e-23");
+    }
+
+
+    #[test]
+    fn result_out_of_range_is_a_typed_error() {
+        // f64 runs out at about 1.8e308
+        let calculator = press(&["number-mode", "number-mode", "1", "0", "power", "4", "0", "0", "equals"]);
+        assert_eq!(calculator.get_number_mode(), "Fast (f64)");
+        assert_eq!(error_code(&calculator), Some("value-out-of-range"));
+        assert!(matches!(calculator.get_preview(), Preview::Invalid(_)));
+    }
+
+
+    #[test]
+    fn definition_preview_knows_its_parameters() {
+        let calculator = press(&["h", "open-paren", "x", "separator", "y", "close-paren", "assign",
+                                 "x", "power", "2", "plus", "y"]);
+        assert_eq!(calculator.get_expression(), "h(x,y)=x^2+y");
+        assert!(matches!(calculator.get_preview(), Preview::Valid));
+
+        let calculator = press(&["h", "open-paren", "x", "close-paren", "assign", "x", "plus"]);
+        assert!(matches!(calculator.get_preview(), Preview::Incomplete));
+    }
+
+
+    #[test]
+    fn digit_after_ans_key_does_not_make_ans_n() {
+        let calculator = press(&["5", "equals", "7", "equals", "ans", "2"]);
+        assert_eq!(calculator.get_expression(), "ans");
+        assert_eq!(calculator.get_result(), "7");
+
+        let calculator = press(&["5", "equals", "7", "equals", "ans", "multiply", "2"]);
+        assert_eq!(calculator.get_result(), "14");
+    }
+
+
+    #[test]
+    fn names_being_typed_are_incomplete() {
+        for keys in [&["p"][..], &["s", "q"], &["2", "m", "o"], &["x", "equals-sign", "3", "equals", "x", "m"]] {
+            assert!(matches!(press(keys).get_preview(), Preview::Incomplete), "{:?}", keys);
+        }
+        assert_eq!(error_code(&press(&["z", "z"])), Some("unknown-variable"));
+
+        let calculator = press(&["l", "o", "r", "e", "m", "equals-sign", "3", "equals", "l", "o", "r", "e", "m"]);
+        assert!(matches!(calculator.get_preview(), Preview::Valid));
+        assert_eq!(calculator.get_result(), "3");
+    }
+
+
+    #[test]
+    fn operator_words_split_only_after_an_operand() {
+        let calculator = press(&["t", "h", "e", "o", "r", "e", "m", "backspace"]);
+        assert_eq!(calculator.get_expression(), "theore");
+        assert_eq!(error_code(&calculator), Some("unknown-variable"));
+
+        let calculator = press(&["programmer-mode", "f", "l", "o", "o", "r", "open-paren", "7", "close-paren", "equals"]);
+        assert_eq!(calculator.get_result(), "7");
+
+        let calculator = press(&["x", "equals-sign", "7", "equals", "x", "m", "o", "d", "3", "equals"]);
+        assert_eq!(calculator.get_result(), "1");
+    }
+
+
+    #[test]
+    fn only_a_typed_double_slash_is_floor_division() {
+        let calculator = press(&["8", "divide", "divide", "3"]);
+        assert_eq!(calculator.get_expression(), "8÷3");
+
+        let calculator = press(&["8", "slash", "slash", "3"]);
+        assert_eq!(calculator.get_expression(), "8//3");
+        assert_eq!(calculator.get_result(), "2");
+    }
+
+
+    #[test]
+    fn conversion_follows_names_typed_without_spaces() {
+        let calculator = press(&["x", "equals-sign", "2", "5", "5", "equals", "x", "i", "n", "h", "e", "x"]);
+        assert_eq!(calculator.get_result(), "0xFF");
+
+        let calculator = press(&["2", "5", "5", "equals", "ans", "i", "n", "h", "e", "x"]);
+        assert_eq!(calculator.get_result(), "0xFF");
+
+        let calculator = press(&["p", "i", "n", "h", "e", "x"]);
+        assert_eq!(error_code(&calculator), Some("unknown-variable"));
+    }
+
+
+    #[test]
+    fn operator_at_caret_replaces_the_one_after_it() {
+        let calculator = press(&["2", "plus", "3", "cursor-left", "cursor-left", "multiply"]);
+        assert_eq!(calculator.get_expression(), "2×3");
+        assert_eq!(calculator.get_cursor(), 2);
+        assert_eq!(calculator.get_result(), "6");
+
+        let calculator = press(&["1", "2", "cursor-left", "5"]);
+        assert_eq!(calculator.get_expression(), "152");
+    }
+
+
+    #[test]
+    fn caret_edits_remove_whole_tokens() {
+        let calculator = press(&["1", "2", "3", "cursor-left", "backspace"]);
+        assert_eq!(calculator.get_expression(), "13");
+        assert_eq!(calculator.get_cursor(), 1);
+
+        let calculator = press(&["1", "2", "3", "cursor-left", "cursor-left", "delete"]);
+        assert_eq!(calculator.get_expression(), "13");
+
+        let calculator = press(&["sqrt", "9", "cursor-left", "backspace"]);
+        assert_eq!(calculator.get_expression(), "9");
+        assert_eq!(calculator.get_cursor(), 0);
+    }
+
+
+    #[test]
+    fn undo_and_redo_step_through_edits() {
+        let mut calculator = press(&["1", "2", "plus", "3", "undo"]);
+        assert_eq!(calculator.get_expression(), "12+");
+
+        handle_calculator_input("undo", &mut calculator);
+        assert_eq!(calculator.get_expression(), "12");
+        handle_calculator_input("redo", &mut calculator);
+        assert_eq!(calculator.get_expression(), "12+");
+
+        let calculator = press(&["1", "plus", "2", "clear", "undo"]);
+        assert_eq!(calculator.get_expression(), "1+2");
+        assert_eq!(calculator.get_result(), "3");
+    }
+
+
+    #[test]
+    fn register_settings_rewrap_a_finished_result() {
+        let mut calculator = press(&["programmer-mode", "word-size", "word-size", "minus", "1", "equals"]);
+        assert_eq!(calculator.get_word_size(), "8-bit");
+        assert_eq!(calculator.get_result(), "-1");
+
+        handle_calculator_input("signedness", &mut calculator);
+        assert_eq!(calculator.get_result(), "255");
+        assert_eq!(calculator.get_word_pattern(), "FF");
+    }
+
+
+    #[test]
+    fn typed_equals_sign_defines_variables() {
+        let calculator = press(&["x", "equals-sign", "1", "2", "decimal", "5", "equals"]);
+        assert!(calculator.get_variables().contains(&("x".to_string(), "12.5".to_string())));
+
+        let calculator = press(&["r", "a", "t", "e", "assign", "equals-sign", "0", "decimal", "0", "7", "equals"]);
+        assert!(calculator.get_variables().contains(&("rate".to_string(), "0.07".to_string())));
+
+        let calculator = press(&["2", "plus", "3", "equals-sign"]);
+        assert_eq!(calculator.get_result(), "5");
+    }
+
+
+    #[test]
+    fn digit_after_memory_recall_does_not_extend_it() {
+        let calculator = press(&["1", "5", "0", "memory-store", "clear", "memory-recall", "3"]);
+        assert_eq!(calculator.get_expression(), "(150)3");
+        assert_eq!(calculator.get_result(), "450");
+    }
+
+
+    #[test]
+    fn memory_recall_brings_back_the_exact_value() {
+        let calculator = press(&["1", "divide", "3", "memory-store", "memory-recall", "multiply", "3", "equals"]);
+        assert_eq!(calculator.get_result(), "1");
+
+        let calculator = press(&["number-mode", "1", "divide", "3", "memory-store", "memory-recall"]);
+        assert_eq!(calculator.get_expression(), "(1÷3)");
+    }
+
+
+    #[test]
+    fn stored_values_round_trip_exactly() {
+        let mut calculator = calculator();
+        calculator.toggle_number_mode();
+        assert_eq!(calculator.get_number_mode(), "Exact (fraction)");
+
+        let values: Vec<Number> = ["1÷3", "-7÷2", "2^100"]
+            .iter()
+            .map(|expression| calculator.evaluate_expression(expression).unwrap())
+            .chain([Number::parse("0.1", NumberMode::Precise).unwrap(), Number::Float(0.1), Number::imaginary_unit()])
+            .collect();
+
+        calculator.toggle_number_mode();
+        calculator.toggle_number_mode();
+        assert_eq!(calculator.get_number_mode(), "Precise (decimal)");
+        for value in values {
+            assert!(calculator.stored_value(&value.to_stored()).unwrap() == value, "{}", value.to_expression());
+        }
+    }
 }
\ No newline at end of file
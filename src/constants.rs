@@ -0,0 +1,55 @@
+pub struct Constant {
+    pub name: &'static str,
+    // Decimal literal, so Precise and Exact modes get every published digit
+    pub value: &'static str,
+    pub unit: &'static str,
+    pub description: &'static str,
+}
+
+
+
+// Physical values are the CODATA 2018 recommended ones; SI-defining constants are exact
+pub const CONSTANTS: &[Constant] = &[
+    Constant { name: "pi", value: "3.14159265358979323846264338327950288419716939937510582097494459", unit: "", description: "Ratio of a circle's circumference to its diameter" },
+    Constant { name: "tau", value: "6.28318530717958647692528676655900576839433879875021164194988918", unit: "", description: "Full turn, 2π" },
+    Constant { name: "e", value: "2.71828182845904523536028747135266249775724709369995957496696763", unit: "", description: "Euler's number, base of the natural logarithm" },
+    Constant { name: "phi", value: "1.61803398874989484820458683436563811772030917980576286213544862", unit: "", description: "Golden ratio" },
+
+    Constant { name: "c", value: "299792458", unit: "m/s", description: "Speed of light in vacuum" },
+    Constant { name: "h", value: "6.62607015e-34", unit: "J·s", description: "Planck constant" },
+    Constant { name: "hbar", value: "1.054571817e-34", unit: "J·s", description: "Reduced Planck constant" },
+    Constant { name: "G", value: "6.67430e-11", unit: "m³/(kg·s²)", description: "Newtonian constant of gravitation" },
+    Constant { name: "g_n", value: "9.80665", unit: "m/s²", description: "Standard acceleration of gravity" },
+    Constant { name: "N_A", value: "6.02214076e23", unit: "1/mol", description: "Avogadro constant" },
+    Constant { name: "k_B", value: "1.380649e-23", unit: "J/K", description: "Boltzmann constant" },
+    Constant { name: "R", value: "8.314462618", unit: "J/(mol·K)", description: "Molar gas constant" },
+    Constant { name: "q_e", value: "1.602176634e-19", unit: "C", description: "Elementary charge" },
+    Constant { name: "F", value: "96485.33212", unit: "C/mol", description: "Faraday constant" },
+    Constant { name: "m_e", value: "9.1093837015e-31", unit: "kg", description: "Electron mass" },
+    Constant { name: "m_p", value: "1.67262192369e-27", unit: "kg", description: "Proton mass" },
+    Constant { name: "m_n", value: "1.67492749804e-27", unit: "kg", description: "Neutron mass" },
+    Constant { name: "m_u", value: "1.66053906660e-27", unit: "kg", description: "Atomic mass constant" },
+    Constant { name: "eps_0", value: "8.8541878128e-12", unit: "F/m", description: "Vacuum electric permittivity" },
+    Constant { name: "mu_0", value: "1.25663706212e-6", unit: "N/A²", description: "Vacuum magnetic permeability" },
+    Constant { name: "alpha", value: "7.2973525693e-3", unit: "", description: "Fine-structure constant" },
+    Constant { name: "a_0", value: "5.29177210903e-11", unit: "m", description: "Bohr radius" },
+    Constant { name: "R_inf", value: "10973731.568160", unit: "1/m", description: "Rydberg constant" },
+    Constant { name: "sigma", value: "5.670374419e-8", unit: "W/(m²·K⁴)", description: "Stefan–Boltzmann constant" },
+];
+
+
+
+pub fn find_constant(name: &str) -> Option<&'static Constant> {
+    CONSTANTS.iter().find(|c| c.name == name)
+}
+
+
+
+// Case-insensitive match on the name or the description; an empty query lists everything
+pub fn search_constants(query: &str) -> Vec<&'static Constant> {
+    let query = query.trim().to_lowercase();
+    CONSTANTS
+        .iter()
+        .filter(|c| c.name.to_lowercase().contains(&query) || c.description.to_lowercase().contains(&query))
+        .collect()
+}
@@ -0,0 +1,178 @@
+use std::fmt;
+
+
+// Byte range into the text that was parsed, so the UI can underline the offending token
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+
+
+// Every failure the calculator can report; the data is kept apart from the wording so a translation
+// only has to match on the variant
+#[derive(Clone, Debug, PartialEq)]
+pub enum ErrorKind {
+    UnexpectedCharacter,
+    UnexpectedEnd,
+    IncompleteExpression,
+    MissingClosingParenthesis,
+    MissingArguments(String),
+    InvalidNumber,
+    UnknownVariable(String),
+    UnknownFunction(String),
+    ArgumentCount { name: String, expected: usize, found: usize },
+    RecursionLimit { name: String, limit: usize },
+    NestingTooDeep(usize),
+    MissingHistoryEntry(String),
+    DivisionByZero,
+    ComplexArgument(String),
+    ComplexFactorial,
+    ComplexIntegerDivision,
+    OutOfRange { function: String, argument: f64 },
+    Undefined { function: String, argument: f64 },
+    FactorialTooLarge(String),
+    TooManyDigits(u64),
+    NegativeFactorial,
+    ValueOutOfRange,
+    InvalidVariableName(String),
+    InvalidSignature(String),
+    EmptyFunctionBody,
+    NotAnInteger(String),
+    ShiftOutOfRange(String),
+    InvalidBase(String),
+    InvalidSlotName(String),
+    NothingToStore,
+}
+
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CalcError {
+    pub kind: ErrorKind,
+    pub span: Option<Span>,
+}
+
+
+
+impl ErrorKind {
+    // Input that more typing can still fix: `2+`, `sin(30`, `sqrt`
+    pub fn is_incomplete(&self) -> bool {
+        matches!(self, ErrorKind::UnexpectedEnd | ErrorKind::IncompleteExpression |
+                       ErrorKind::MissingClosingParenthesis | ErrorKind::MissingArguments(_))
+    }
+
+
+    // Stable identifier for embedders and translation tables
+    pub fn code(&self) -> &'static str {
+        match self {
+            ErrorKind::UnexpectedCharacter => "unexpected-character",
+            ErrorKind::UnexpectedEnd => "unexpected-end",
+            ErrorKind::IncompleteExpression => "incomplete-expression",
+            ErrorKind::MissingClosingParenthesis => "missing-closing-parenthesis",
+            ErrorKind::MissingArguments(_) => "missing-arguments",
+            ErrorKind::InvalidNumber => "invalid-number",
+            ErrorKind::UnknownVariable(_) => "unknown-variable",
+            ErrorKind::UnknownFunction(_) => "unknown-function",
+            ErrorKind::ArgumentCount { .. } => "argument-count",
+            ErrorKind::RecursionLimit { .. } => "recursion-limit",
+            ErrorKind::NestingTooDeep(_) => "nesting-too-deep",
+            ErrorKind::MissingHistoryEntry(_) => "missing-history-entry",
+            ErrorKind::DivisionByZero => "division-by-zero",
+            ErrorKind::ComplexArgument(_) => "complex-argument",
+            ErrorKind::ComplexFactorial => "complex-factorial",
+            ErrorKind::ComplexIntegerDivision => "complex-integer-division",
+            ErrorKind::OutOfRange { .. } => "out-of-range",
+            ErrorKind::Undefined { .. } => "undefined",
+            ErrorKind::FactorialTooLarge(_) => "factorial-too-large",
+            ErrorKind::TooManyDigits(_) => "too-many-digits",
+            ErrorKind::NegativeFactorial => "negative-factorial",
+            ErrorKind::ValueOutOfRange => "value-out-of-range",
+            ErrorKind::InvalidVariableName(_) => "invalid-variable-name",
+            ErrorKind::InvalidSignature(_) => "invalid-signature",
+            ErrorKind::EmptyFunctionBody => "empty-function-body",
+            ErrorKind::NotAnInteger(_) => "not-an-integer",
+            ErrorKind::ShiftOutOfRange(_) => "shift-out-of-range",
+            ErrorKind::InvalidBase(_) => "invalid-base",
+            ErrorKind::InvalidSlotName(_) => "invalid-slot-name",
+            ErrorKind::NothingToStore => "nothing-to-store",
+        }
+    }
+}
+
+
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorKind::UnexpectedCharacter => write!(f, "Unexpected character"),
+            ErrorKind::UnexpectedEnd => write!(f, "Unexpected end of expression"),
+            ErrorKind::IncompleteExpression => write!(f, "Incomplete expression"),
+            ErrorKind::MissingClosingParenthesis => write!(f, "Missing closing parenthesis"),
+            ErrorKind::MissingArguments(name) => write!(f, "Missing '(' after {}", name),
+            ErrorKind::InvalidNumber => write!(f, "Invalid number"),
+            ErrorKind::UnknownVariable(name) => write!(f, "Unknown variable: {}", name),
+            ErrorKind::UnknownFunction(name) => write!(f, "Unknown function: {}", name),
+            ErrorKind::ArgumentCount { name, expected: 1, found } => write!(f, "{} takes one argument, got {}", name, found),
+            ErrorKind::ArgumentCount { name, expected, found } => write!(f, "{} takes {} arguments, got {}", name, expected, found),
+            ErrorKind::RecursionLimit { name, limit } => write!(f, "Recursion in {} is deeper than {} calls", name, limit),
+            ErrorKind::NestingTooDeep(limit) => write!(f, "Expression is nested deeper than {} levels", limit),
+            ErrorKind::MissingHistoryEntry(reference) => write!(f, "No result {} in the history", reference),
+            ErrorKind::DivisionByZero => write!(f, "Division by zero"),
+            ErrorKind::ComplexArgument(name) => write!(f, "{} is not defined for complex numbers", name),
+            ErrorKind::ComplexFactorial => write!(f, "Factorial is not defined for complex numbers"),
+            ErrorKind::ComplexIntegerDivision => write!(f, "Integer division is not defined for complex numbers"),
+            ErrorKind::OutOfRange { function, argument } => write!(f, "{}({}) is out of range", function, argument),
+            ErrorKind::Undefined { function, argument } => write!(f, "{}({}) is undefined", function, argument),
+            ErrorKind::FactorialTooLarge(n) => write!(f, "{}! is too large", n),
+            ErrorKind::TooManyDigits(digits) => write!(f, "The exact result would have about {} digits", digits),
+            ErrorKind::NegativeFactorial => write!(f, "Factorial is undefined for negative integers"),
+            ErrorKind::ValueOutOfRange => write!(f, "Value is out of range"),
+            ErrorKind::InvalidVariableName(name) => write!(f, "Invalid variable name: {}", name),
+            ErrorKind::InvalidSignature(signature) => write!(f, "Invalid function signature: {}", signature),
+            ErrorKind::EmptyFunctionBody => write!(f, "Function body is empty"),
+            ErrorKind::NotAnInteger(value) => write!(f, "{} is not an integer", value),
+            ErrorKind::ShiftOutOfRange(count) => write!(f, "Shift by {} is out of range", count),
+            ErrorKind::InvalidBase(base) => write!(f, "Base {} is not between 2 and 36", base),
+            ErrorKind::InvalidSlotName(name) => write!(f, "Invalid memory slot name: {}", name),
+            ErrorKind::NothingToStore => write!(f, "There is no value to store"),
+        }
+    }
+}
+
+
+
+impl CalcError {
+    pub fn at(kind: ErrorKind, span: Span) -> Self {
+        Self { kind, span: Some(span) }
+    }
+
+
+    // The innermost node to fail knows the offending token best, so an existing span is kept
+    pub fn or_span(mut self, span: Span) -> Self {
+        self.span.get_or_insert(span);
+        self
+    }
+
+
+    pub fn code(&self) -> &'static str {
+        self.kind.code()
+    }
+}
+
+
+
+impl From<ErrorKind> for CalcError {
+    fn from(kind: ErrorKind) -> Self {
+        Self { kind, span: None }
+    }
+}
+
+
+
+impl fmt::Display for CalcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.kind.fmt(f)
+    }
+}
@@ -0,0 +1,298 @@
+use std::f64::consts::PI;
+
+use bigdecimal::{BigDecimal, RoundingMode};
+use num_bigint::BigInt;
+use num_complex::Complex64;
+use num_rational::BigRational;
+use num_traits::ToPrimitive;
+
+use crate::error::{CalcError, ErrorKind};
+use crate::number::{MAX_EXACT_DIGITS, Number};
+
+
+// Last factorial that fits in an f64
+const MAX_F64_FACTORIAL: i64 = 170;
+const LANCZOS_G: f64 = 7.0;
+const LANCZOS_COEFFICIENTS: [f64; 9] = [
+    0.999_999_999_999_809_9,
+    676.520_368_121_885_1,
+    -1_259.139_216_722_402_8,
+    771.323_428_777_653_1,
+    -176.615_029_162_140_6,
+    12.507_343_278_686_905,
+    -0.138_571_095_265_720_12,
+    9.984_369_578_019_572e-6,
+    1.505_632_735_149_311_6e-7,
+];
+
+
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum AngleMode {
+    Degrees,
+    Radians,
+    Gradians,
+}
+
+
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum FunctionKind {
+    Plain,
+    TakesAngle,
+    ReturnsAngle,
+}
+
+
+
+pub struct MathFunction {
+    pub name: &'static str,
+    pub apply: fn(f64) -> f64,
+    pub apply_complex: Option<fn(Complex64) -> Complex64>,
+    // Worked out without f64 for the arguments it covers, so fractions and long decimals keep every digit
+    pub exact: Option<fn(&Number) -> Option<Number>>,
+    pub kind: FunctionKind,
+}
+
+
+
+impl AngleMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AngleMode::Degrees => "DEG",
+            AngleMode::Radians => "RAD",
+            AngleMode::Gradians => "GRAD",
+        }
+    }
+
+
+    pub fn next(&self) -> Self {
+        match self {
+            AngleMode::Degrees => AngleMode::Radians,
+            AngleMode::Radians => AngleMode::Gradians,
+            AngleMode::Gradians => AngleMode::Degrees,
+        }
+    }
+
+
+    // Explicit unit suffixes on values: `30°`, `1.2rad`, `50grad`
+    pub fn from_suffix(suffix: &str) -> Option<Self> {
+        match suffix {
+            "°" => Some(AngleMode::Degrees),
+            "rad" => Some(AngleMode::Radians),
+            "grad" => Some(AngleMode::Gradians),
+            _ => None,
+        }
+    }
+
+
+    fn full_turn(&self) -> f64 {
+        match self {
+            AngleMode::Degrees => 360.0,
+            AngleMode::Radians => 2.0 * PI,
+            AngleMode::Gradians => 400.0,
+        }
+    }
+
+
+    pub fn convert(&self, value: f64, to: AngleMode) -> f64 {
+        if *self == to {
+            return value;
+        }
+        value / self.full_turn() * to.full_turn()
+    }
+
+
+    fn convert_complex(&self, value: Complex64, to: AngleMode) -> Complex64 {
+        if *self == to {
+            return value;
+        }
+        value.scale(to.full_turn() / self.full_turn())
+    }
+}
+
+
+
+impl MathFunction {
+    // Real arguments outside the real domain (`sqrt(-4)`, `ln(-1)`) continue in the complex plane
+    pub fn evaluate(&self, argument: &Number, angle_mode: AngleMode) -> Result<Number, CalcError> {
+        if let Number::Complex(z) = argument {
+            let apply_complex = self.apply_complex
+                .ok_or_else(|| ErrorKind::ComplexArgument(self.name.to_string()))?;
+            return Ok(Number::from_complex(self.call_complex(apply_complex, *z, angle_mode), argument));
+        }
+
+        if let Some(value) = self.exact.and_then(|exact| exact(argument)) {
+            return Ok(value);
+        }
+
+        let x = argument.to_f64();
+        let result = self.call(x, angle_mode);
+        if result.is_infinite() && x.is_finite() {
+            return Err(ErrorKind::OutOfRange { function: self.name.to_string(), argument: x }.into());
+        }
+
+        match self.apply_complex {
+            Some(apply_complex) if result.is_nan() && !x.is_nan() => {
+                let z = Complex64::new(x, 0.0);
+                Ok(Number::from_complex(self.call_complex(apply_complex, z, angle_mode), argument))
+            },
+            None if result.is_nan() && !x.is_nan() => Err(ErrorKind::Undefined { function: self.name.to_string(), argument: x }.into()),
+            _ => Ok(argument.with_f64(result)),
+        }
+    }
+
+
+    fn call(&self, argument: f64, angle_mode: AngleMode) -> f64 {
+        match self.kind {
+            FunctionKind::Plain => (self.apply)(argument),
+            FunctionKind::TakesAngle => {
+                let radians = angle_mode.convert(argument, AngleMode::Radians);
+                let result = (self.apply)(radians);
+                // `sin(180°)` misses zero by 1.2e-16 only because π has no exact f64
+                if result.abs() < radians.abs() * f64::EPSILON { 0.0 } else { result }
+            },
+            FunctionKind::ReturnsAngle => AngleMode::Radians.convert((self.apply)(argument), angle_mode),
+        }
+    }
+
+
+    fn call_complex(&self, apply: fn(Complex64) -> Complex64, argument: Complex64, angle_mode: AngleMode) -> Complex64 {
+        match self.kind {
+            FunctionKind::Plain => apply(argument),
+            FunctionKind::TakesAngle => apply(angle_mode.convert_complex(argument, AngleMode::Radians)),
+            FunctionKind::ReturnsAngle => AngleMode::Radians.convert_complex(apply(argument), angle_mode),
+        }
+    }
+}
+
+
+
+// To add a function, add a row here; the parser and the keypad pick it up by name
+pub const FUNCTIONS: &[MathFunction] = &[
+    MathFunction { name: "sqrt", apply: f64::sqrt, apply_complex: Some(Complex64::sqrt), exact: Some(Number::sqrt), kind: FunctionKind::Plain },
+    MathFunction { name: "cbrt", apply: f64::cbrt, apply_complex: Some(Complex64::cbrt), exact: Some(Number::cbrt), kind: FunctionKind::Plain },
+
+    MathFunction { name: "sin", apply: f64::sin, apply_complex: Some(Complex64::sin), exact: None, kind: FunctionKind::TakesAngle },
+    MathFunction { name: "cos", apply: f64::cos, apply_complex: Some(Complex64::cos), exact: None, kind: FunctionKind::TakesAngle },
+    MathFunction { name: "tan", apply: f64::tan, apply_complex: Some(Complex64::tan), exact: None, kind: FunctionKind::TakesAngle },
+    MathFunction { name: "asin", apply: f64::asin, apply_complex: Some(Complex64::asin), exact: None, kind: FunctionKind::ReturnsAngle },
+    MathFunction { name: "acos", apply: f64::acos, apply_complex: Some(Complex64::acos), exact: None, kind: FunctionKind::ReturnsAngle },
+    MathFunction { name: "atan", apply: f64::atan, apply_complex: Some(Complex64::atan), exact: None, kind: FunctionKind::ReturnsAngle },
+
+    MathFunction { name: "sinh", apply: f64::sinh, apply_complex: Some(Complex64::sinh), exact: None, kind: FunctionKind::Plain },
+    MathFunction { name: "cosh", apply: f64::cosh, apply_complex: Some(Complex64::cosh), exact: None, kind: FunctionKind::Plain },
+    MathFunction { name: "tanh", apply: f64::tanh, apply_complex: Some(Complex64::tanh), exact: None, kind: FunctionKind::Plain },
+    MathFunction { name: "asinh", apply: f64::asinh, apply_complex: Some(Complex64::asinh), exact: None, kind: FunctionKind::Plain },
+    MathFunction { name: "acosh", apply: f64::acosh, apply_complex: Some(Complex64::acosh), exact: None, kind: FunctionKind::Plain },
+    MathFunction { name: "atanh", apply: f64::atanh, apply_complex: Some(Complex64::atanh), exact: None, kind: FunctionKind::Plain },
+
+    MathFunction { name: "ln", apply: f64::ln, apply_complex: Some(Complex64::ln), exact: None, kind: FunctionKind::Plain },
+    MathFunction { name: "log", apply: f64::log10, apply_complex: Some(Complex64::log10), exact: None, kind: FunctionKind::Plain },
+    MathFunction { name: "log10", apply: f64::log10, apply_complex: Some(Complex64::log10), exact: None, kind: FunctionKind::Plain },
+    MathFunction { name: "log2", apply: f64::log2, apply_complex: Some(Complex64::log2), exact: None, kind: FunctionKind::Plain },
+    MathFunction { name: "exp", apply: f64::exp, apply_complex: Some(Complex64::exp), exact: None, kind: FunctionKind::Plain },
+    MathFunction { name: "gamma", apply: gamma, apply_complex: None, exact: None, kind: FunctionKind::Plain },
+
+    MathFunction { name: "abs", apply: f64::abs, apply_complex: Some(|z| Complex64::new(z.norm(), 0.0)), exact: Some(|x| Some(x.abs())), kind: FunctionKind::Plain },
+    MathFunction { name: "floor", apply: f64::floor, apply_complex: None, exact: Some(|x| x.clone().round_toward(RoundingMode::Floor).ok()), kind: FunctionKind::Plain },
+    MathFunction { name: "ceil", apply: f64::ceil, apply_complex: None, exact: Some(|x| x.clone().round_toward(RoundingMode::Ceiling).ok()), kind: FunctionKind::Plain },
+    MathFunction { name: "round", apply: f64::round, apply_complex: None, exact: Some(|x| x.clone().round_toward(RoundingMode::HalfUp).ok()), kind: FunctionKind::Plain },
+
+    MathFunction { name: "sign", apply: |x| if x == 0.0 { 0.0 } else { x.signum() }, apply_complex: Some(|z| if z.norm() == 0.0 { z } else { z / z.norm() }), exact: Some(|x| Some(x.signum())), kind: FunctionKind::Plain },
+
+    MathFunction { name: "re", apply: |x| x, apply_complex: Some(|z| Complex64::new(z.re, 0.0)), exact: Some(|x| Some(x.clone())), kind: FunctionKind::Plain },
+    MathFunction { name: "im", apply: |_| 0.0, apply_complex: Some(|z| Complex64::new(z.im, 0.0)), exact: None, kind: FunctionKind::Plain },
+    MathFunction { name: "arg", apply: |x| if x < 0.0 { PI } else { 0.0 }, apply_complex: Some(|z| Complex64::new(z.arg(), 0.0)), exact: None, kind: FunctionKind::ReturnsAngle },
+    MathFunction { name: "conj", apply: |x| x, apply_complex: Some(|z| z.conj()), exact: Some(|x| Some(x.clone())), kind: FunctionKind::Plain },
+];
+
+
+
+pub fn find_function(name: &str) -> Option<&'static MathFunction> {
+    FUNCTIONS.iter().find(|f| f.name == name)
+}
+
+
+
+// Postfix `!`: whole numbers are multiplied out exactly, anything else is gamma(x + 1)
+pub fn factorial(value: &Number) -> Result<Number, CalcError> {
+    let whole = match value {
+        Number::Rational(v) if v.is_integer() => v.to_integer().to_i64(),
+        Number::Decimal(v) if v.is_integer() => v.to_i64(),
+        Number::Float(v) if v.fract() == 0.0 => v.to_i64(),
+        Number::Complex(_) => return Err(ErrorKind::ComplexFactorial.into()),
+        _ => None,
+    };
+
+    let Some(n) = whole else {
+        let x = value.to_f64();
+        let result = gamma(x + 1.0);
+        if !result.is_finite() {
+            return Err(ErrorKind::FactorialTooLarge(x.to_string()).into());
+        }
+        return Ok(value.with_f64(result));
+    };
+
+    if n < 0 {
+        return Err(ErrorKind::NegativeFactorial.into());
+    }
+    if matches!(value, Number::Float(_)) && n > MAX_F64_FACTORIAL {
+        return Err(ErrorKind::FactorialTooLarge(n.to_string()).into());
+    }
+    // Same budget as exact powers; Stirling's formula gives the digits without multiplying anything out
+    let digits = if n < 2 { 1.0 } else { ((n as f64 + 0.5) * (n as f64).ln() - n as f64 + (2.0 * PI).sqrt().ln()) / std::f64::consts::LN_10 };
+    if digits > MAX_EXACT_DIGITS {
+        return Err(ErrorKind::TooManyDigits(digits as u64).into());
+    }
+
+    let product: BigInt = (1..=n).map(BigInt::from).product();
+    Ok(match value {
+        Number::Rational(_) => Number::Rational(BigRational::from_integer(product)),
+        Number::Decimal(_) => Number::Decimal(BigDecimal::from(product)),
+        _ => Number::Float(product.to_f64().unwrap_or(f64::INFINITY)),
+    })
+}
+
+
+
+// Lanczos approximation, with the reflection formula below 0.5; poles come back as NaN
+pub fn gamma(x: f64) -> f64 {
+    if x <= 0.0 && x.fract() == 0.0 {
+        return f64::NAN;
+    }
+    if x.fract() == 0.0 && x <= (MAX_F64_FACTORIAL + 1) as f64 {
+        return (1..x as i64).map(|k| k as f64).product();
+    }
+    if x < 0.5 {
+        return PI / ((PI * x).sin() * gamma(1.0 - x));
+    }
+
+    let x = x - 1.0;
+    let sum = LANCZOS_COEFFICIENTS
+        .iter()
+        .enumerate()
+        .skip(1)
+        .fold(LANCZOS_COEFFICIENTS[0], |sum, (k, c)| sum + c / (x + k as f64));
+    let t = x + LANCZOS_G + 0.5;
+    // t^(x+0.5) is split in two so it does not overflow before the exp(-t) brings it back down
+    let half_power = t.powf((x + 0.5) / 2.0);
+    (2.0 * PI).sqrt() * half_power * (-t).exp() * half_power * sum
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::number::NumberMode;
+
+
+    #[test]
+    fn exact_factorials_share_the_digit_budget() {
+        let exact = |literal: &str| factorial(&Number::parse(literal, NumberMode::Exact).unwrap());
+        assert_eq!(exact("20").unwrap().to_expression(), "2432902008176640000");
+        assert!(exact("10000").is_ok());
+        assert_eq!(exact("12000").unwrap_err().code(), "too-many-digits");
+    }
+}
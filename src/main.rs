@@ -9,14 +9,23 @@ slint::include_modules!();
 mod utils {
     pub mod system_utils;
     pub mod logger;
+    pub mod storage;
 }
 
 
 mod calculator;
+mod constants;
+mod error;
+mod functions;
+mod number;
+mod parser;
+mod programmer;
+mod tokens;
 
 
 use crate::utils::logger::LOGGER;
-use crate::calculator::{Calculator, handle_calculator_input};
+use crate::calculator::{Calculator, Preview, handle_calculator_input};
+use crate::constants::search_constants;
 
 
 
@@ -47,8 +56,11 @@ fn main() -> Result<(), slint::PlatformError> {
 
     // calc init
     let mut calculator = Calculator::new();
-    main_window.set_expression_text(slint::SharedString::from(""));
-    main_window.set_result_text(slint::SharedString::from("0"));
+    calculator.load_variables();
+    calculator.load_functions();
+    calculator.load_memory();
+    update_display(&main_window, &calculator);
+    show_constants(&main_window, "");
     
     let calculator_rc = std::rc::Rc::new(std::cell::RefCell::new(calculator));
     let calculator_for_buttons = calculator_rc.clone();
@@ -70,11 +82,198 @@ fn main() -> Result<(), slint::PlatformError> {
         
         handle_calculator_input(button_id.as_str(), &mut calc);
         
-        main_window.set_expression_text(slint::SharedString::from(calc.get_expression()));
-        main_window.set_result_text(slint::SharedString::from(calc.get_result()));
+        update_display(&main_window, &calc);
         
         LOGGER.info(&format!("Calculator button pressed: Type: {}, ID: {}", type_str, button_id.as_str()));
     });
+
+
+    // Clicks on the expression move the caret
+    let calculator_for_cursor = calculator_rc.clone();
+    let main_window_weak_cursor = main_window.as_weak();
+
+    main_window.on_cursor_placed(move |index| {
+        let main_window = main_window_weak_cursor.upgrade().unwrap();
+        let mut calc = calculator_for_cursor.borrow_mut();
+
+        calc.set_cursor(index.max(0) as usize);
+        update_display(&main_window, &calc);
+    });
+
+
+    // Variables panel
+    let calculator_for_edit = calculator_rc.clone();
+    let main_window_weak_edit = main_window.as_weak();
+
+    main_window.on_variable_edited(move |name, value| {
+        let main_window = main_window_weak_edit.upgrade().unwrap();
+        let mut calc = calculator_for_edit.borrow_mut();
+
+        match calc.set_variable(name.trim(), value.as_str()) {
+            Ok(()) => LOGGER.info(&format!("Variable edited: {}", name)),
+            Err(e) => LOGGER.warn(&format!("Variable {} not set: {}", name, e)),
+        }
+
+        update_display(&main_window, &calc);
+    });
+
+    let calculator_for_delete = calculator_rc.clone();
+    let main_window_weak_delete = main_window.as_weak();
+
+    main_window.on_variable_deleted(move |name| {
+        let main_window = main_window_weak_delete.upgrade().unwrap();
+        let mut calc = calculator_for_delete.borrow_mut();
+
+        calc.delete_variable(name.as_str());
+        update_display(&main_window, &calc);
+
+        LOGGER.info(&format!("Variable deleted: {}", name));
+    });
+
+    let calculator_for_insert = calculator_rc.clone();
+    let main_window_weak_insert = main_window.as_weak();
+
+    main_window.on_variable_inserted(move |name| {
+        let main_window = main_window_weak_insert.upgrade().unwrap();
+        let mut calc = calculator_for_insert.borrow_mut();
+
+        calc.add_name(name.as_str());
+        update_display(&main_window, &calc);
+    });
+
+
+    // Functions section of the settings menu
+    let calculator_for_function_edit = calculator_rc.clone();
+    let main_window_weak_function_edit = main_window.as_weak();
+
+    main_window.on_function_edited(move |signature, body| {
+        let main_window = main_window_weak_function_edit.upgrade().unwrap();
+        let mut calc = calculator_for_function_edit.borrow_mut();
+
+        match calc.define_function(signature.as_str(), body.as_str()) {
+            Ok(()) => LOGGER.info(&format!("Function edited: {}", signature)),
+            Err(e) => LOGGER.warn(&format!("Function {} not defined: {}", signature, e)),
+        }
+
+        update_display(&main_window, &calc);
+    });
+
+    let calculator_for_function_delete = calculator_rc.clone();
+    let main_window_weak_function_delete = main_window.as_weak();
+
+    main_window.on_function_deleted(move |name| {
+        let main_window = main_window_weak_function_delete.upgrade().unwrap();
+        let mut calc = calculator_for_function_delete.borrow_mut();
+
+        calc.delete_function(name.as_str());
+        update_display(&main_window, &calc);
+
+        LOGGER.info(&format!("Function deleted: {}", name));
+    });
+    
+
+    // Constants picker
+    let main_window_weak_search = main_window.as_weak();
+
+    main_window.on_constants_searched(move |query| {
+        let main_window = main_window_weak_search.upgrade().unwrap();
+        show_constants(&main_window, query.as_str());
+    });
+
+    let calculator_for_constant = calculator_rc.clone();
+    let main_window_weak_constant = main_window.as_weak();
+
+    main_window.on_constant_inserted(move |name| {
+        let main_window = main_window_weak_constant.upgrade().unwrap();
+        let mut calc = calculator_for_constant.borrow_mut();
+
+        calc.add_name(name.as_str());
+        update_display(&main_window, &calc);
+    });
+
+
+    // Base panel
+    main_window.on_value_copied(|value| {
+        LOGGER.info(&format!("Copied to clipboard: {}", value));
+    });
     
     main_window.run()
+}
+
+
+
+fn show_constants(main_window: &MainWindow, query: &str) {
+    let constants: Vec<ConstantItem> = search_constants(query)
+        .into_iter()
+        .map(|c| ConstantItem {
+            name: c.name.into(),
+            value: format!("{} {}", c.value, c.unit).trim_end().into(),
+            description: c.description.into(),
+        })
+        .collect();
+    main_window.set_constants(std::rc::Rc::new(slint::VecModel::from(constants)).into());
+}
+
+
+
+fn update_display(main_window: &MainWindow, calc: &Calculator) {
+    let expression = calc.get_expression();
+    let span = calc.get_error().and_then(|e| e.span).filter(|span| span.start < span.end);
+    let glyphs: Vec<ExpressionGlyph> = expression
+        .char_indices()
+        .map(|(i, c)| ExpressionGlyph {
+            text: c.to_string().into(),
+            error: span.is_some_and(|span| span.start <= i && i < span.end),
+        })
+        .collect();
+    main_window.set_expression_glyphs(std::rc::Rc::new(slint::VecModel::from(glyphs)).into());
+    main_window.set_cursor_position(calc.get_cursor() as i32);
+    main_window.set_ghost_parentheses_text(slint::SharedString::from(calc.get_ghost_parentheses()));
+    main_window.set_entry_label_text(slint::SharedString::from(calc.get_entry_label()));
+    main_window.set_result_text(slint::SharedString::from(calc.get_result()));
+
+    let (preview_state, preview_message) = match calc.get_preview() {
+        Preview::Valid => (PreviewState::Valid, String::new()),
+        Preview::Incomplete => (PreviewState::Incomplete, String::new()),
+        Preview::Invalid(e) => (PreviewState::Invalid, e.to_string()),
+    };
+    main_window.set_preview_state(preview_state);
+    main_window.set_preview_message(slint::SharedString::from(preview_message));
+
+    main_window.set_angle_mode_text(slint::SharedString::from(calc.get_angle_mode()));
+    main_window.set_number_mode_text(slint::SharedString::from(calc.get_number_mode()));
+    main_window.set_implicit_multiplication_text(slint::SharedString::from(calc.get_implicit_multiplication()));
+    main_window.set_auto_close_parentheses_text(slint::SharedString::from(calc.get_auto_close_parentheses()));
+    main_window.set_programmer_mode(calc.get_programmer_mode());
+    main_window.set_word_size_text(slint::SharedString::from(calc.get_word_size()));
+    main_window.set_signedness_text(slint::SharedString::from(calc.get_signedness()));
+    main_window.set_overflow(calc.get_overflow());
+    main_window.set_word_pattern_text(slint::SharedString::from(calc.get_word_pattern()));
+
+    let bases: Vec<BaseItem> = calc.get_base_representations()
+        .into_iter()
+        .map(|(label, value)| BaseItem { label: label.into(), value: value.into() })
+        .collect();
+    main_window.set_bases(std::rc::Rc::new(slint::VecModel::from(bases)).into());
+    main_window.set_fraction_display_text(slint::SharedString::from(calc.get_fraction_display()));
+    main_window.set_complex_display_text(slint::SharedString::from(calc.get_complex_display()));
+
+    let variables: Vec<VariableItem> = calc.get_variables()
+        .into_iter()
+        .map(|(name, value)| VariableItem { name: name.into(), value: value.into() })
+        .collect();
+    main_window.set_variables(std::rc::Rc::new(slint::VecModel::from(variables)).into());
+
+    let functions: Vec<FunctionItem> = calc.get_functions()
+        .into_iter()
+        .map(|(name, signature, body)| FunctionItem { name: name.into(), signature: signature.into(), body: body.into() })
+        .collect();
+    main_window.set_functions(std::rc::Rc::new(slint::VecModel::from(functions)).into());
+
+    main_window.set_memory_indicator(calc.get_memory_indicator());
+    let memory_slots: Vec<VariableItem> = calc.get_memory()
+        .into_iter()
+        .map(|(name, value)| VariableItem { name: name.into(), value: value.into() })
+        .collect();
+    main_window.set_memory_slots(std::rc::Rc::new(slint::VecModel::from(memory_slots)).into());
 }
\ No newline at end of file
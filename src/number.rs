@@ -0,0 +1,679 @@
+use std::ops::{Add, Mul, Neg, Sub};
+use std::str::FromStr;
+
+use crate::error::{CalcError, ErrorKind};
+use bigdecimal::{BigDecimal, RoundingMode};
+use num_bigint::BigInt;
+use num_complex::Complex64;
+use num_rational::BigRational;
+use num_traits::{FromPrimitive, One, Pow, Signed, ToPrimitive, Zero};
+
+
+// Significant digits kept for powers of non-integer bases
+const DECIMAL_PRECISION: u64 = 100;
+// Exact powers and factorials past this many digits would take seconds; `10000!` has 35660
+pub const MAX_EXACT_DIGITS: f64 = 40_000.0;
+const F64_SIGNIFICANT_DIGITS: usize = 15;
+// An f64 result this short once rounded to 15 digits was that short number plus noise
+const CLEAN_F64_DIGITS: u64 = 12;
+const MAX_EXACT_F64_INTEGER: f64 = 9_007_199_254_740_992.0;
+
+
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum NumberMode {
+    Fast,
+    Precise,
+    Exact,
+}
+
+
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ComplexDisplay {
+    Rectangular,
+    Polar,
+}
+
+
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum FractionDisplay {
+    Fraction,
+    Mixed,
+    Decimal,
+}
+
+
+
+// Ordered from most to least exact; mixed operations use the less exact side
+#[derive(Clone, Debug, PartialEq)]
+pub enum Number {
+    Rational(BigRational),
+    Decimal(BigDecimal),
+    Float(f64),
+    Complex(Complex64),
+}
+
+
+
+impl NumberMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NumberMode::Fast => "Fast (f64)",
+            NumberMode::Precise => "Precise (decimal)",
+            NumberMode::Exact => "Exact (fraction)",
+        }
+    }
+
+
+    pub fn next(&self) -> Self {
+        match self {
+            NumberMode::Fast => NumberMode::Precise,
+            NumberMode::Precise => NumberMode::Exact,
+            NumberMode::Exact => NumberMode::Fast,
+        }
+    }
+}
+
+
+
+impl ComplexDisplay {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ComplexDisplay::Rectangular => "a+bi",
+            ComplexDisplay::Polar => "r∠θ",
+        }
+    }
+
+
+    pub fn next(&self) -> Self {
+        match self {
+            ComplexDisplay::Rectangular => ComplexDisplay::Polar,
+            ComplexDisplay::Polar => ComplexDisplay::Rectangular,
+        }
+    }
+}
+
+
+
+impl FractionDisplay {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FractionDisplay::Fraction => "a/b",
+            FractionDisplay::Mixed => "a b/c",
+            FractionDisplay::Decimal => "0.5",
+        }
+    }
+
+
+    pub fn next(&self) -> Self {
+        match self {
+            FractionDisplay::Fraction => FractionDisplay::Mixed,
+            FractionDisplay::Mixed => FractionDisplay::Decimal,
+            FractionDisplay::Decimal => FractionDisplay::Fraction,
+        }
+    }
+}
+
+
+
+impl Number {
+    pub fn parse(literal: &str, mode: NumberMode) -> Result<Self, CalcError> {
+        match mode {
+            NumberMode::Fast => literal.parse::<f64>().map(Number::Float).map_err(|_| ErrorKind::InvalidNumber.into()),
+            NumberMode::Precise => BigDecimal::from_str(literal).map(Number::Decimal).map_err(|_| ErrorKind::InvalidNumber.into()),
+            NumberMode::Exact => BigDecimal::from_str(literal)
+                .map(|decimal| Number::Rational(decimal_to_rational(&decimal)))
+                .map_err(|_| ErrorKind::InvalidNumber.into()),
+        }
+    }
+
+
+    pub fn from_integer(value: BigInt) -> Self {
+        Number::Rational(BigRational::from_integer(value))
+    }
+
+
+    pub fn imaginary_unit() -> Self {
+        Number::Complex(Complex64::i())
+    }
+
+
+    // Complex results that landed back on the real axis take the representation of `like`
+    pub fn from_complex(value: Complex64, like: &Number) -> Self {
+        if value.im == 0.0 {
+            like.with_f64(value.re)
+        } else {
+            Number::Complex(value)
+        }
+    }
+
+
+    pub fn zero(mode: NumberMode) -> Self {
+        match mode {
+            NumberMode::Fast => Number::Float(0.0),
+            NumberMode::Precise => Number::Decimal(BigDecimal::zero()),
+            NumberMode::Exact => Number::Rational(BigRational::zero()),
+        }
+    }
+
+
+    // Full-precision text that parses back to the same value
+    pub fn to_expression(&self) -> String {
+        match self {
+            Number::Rational(value) => value.to_string(),
+            Number::Decimal(value) => value.normalized().to_plain_string(),
+            Number::Float(value) => value.to_string(),
+            Number::Complex(value) if value.im < 0.0 => format!("{}-{}i", value.re, -value.im),
+            Number::Complex(value) => format!("{}+{}i", value.re, value.im),
+        }
+    }
+
+
+    // Tagged storage text, read back as the same representation whatever the mode: `rational:1/3`
+    pub fn to_stored(&self) -> String {
+        match self {
+            Number::Rational(value) => format!("rational:{}", value),
+            Number::Decimal(value) => format!("decimal:{}", value.normalized()),
+            Number::Float(value) => format!("float:{}", value),
+            Number::Complex(value) => format!("complex:{},{}", value.re, value.im),
+        }
+    }
+
+
+    pub fn from_stored(text: &str) -> Option<Self> {
+        let (kind, value) = text.split_once(':')?;
+        match kind {
+            "rational" => BigRational::from_str(value).ok().map(Number::Rational),
+            "decimal" => BigDecimal::from_str(value).ok().map(Number::Decimal),
+            "float" => value.parse().ok().map(Number::Float),
+            "complex" => {
+                let (re, im) = value.split_once(',')?;
+                Some(Number::Complex(Complex64::new(re.parse().ok()?, im.parse().ok()?)))
+            },
+            _ => None,
+        }
+    }
+
+
+    pub fn to_f64(&self) -> f64 {
+        match self {
+            Number::Rational(value) => value.to_f64().unwrap_or(f64::NAN),
+            Number::Decimal(value) => value.to_f64().unwrap_or(f64::NAN),
+            Number::Float(value) => *value,
+            Number::Complex(value) if value.im == 0.0 => value.re,
+            Number::Complex(_) => f64::NAN,
+        }
+    }
+
+
+    // Whole numbers only; `2.5` and `3+i` have no bits to work on
+    pub fn to_integer(&self) -> Option<BigInt> {
+        match self {
+            Number::Rational(value) if value.is_integer() => Some(value.to_integer()),
+            Number::Decimal(value) if value.is_integer() => Some(value.with_scale(0).into_bigint_and_exponent().0),
+            Number::Float(value) if value.fract() == 0.0 => BigInt::from_f64(*value),
+            _ => None,
+        }
+    }
+
+
+    fn to_complex(&self) -> Complex64 {
+        match self {
+            Number::Complex(value) => *value,
+            other => Complex64::new(other.to_f64(), 0.0),
+        }
+    }
+
+
+    fn to_decimal(&self) -> Option<BigDecimal> {
+        match self {
+            Number::Rational(value) => Some(rational_to_decimal(value)),
+            Number::Decimal(value) => Some(value.clone()),
+            Number::Float(_) | Number::Complex(_) => None,
+        }
+    }
+
+
+    // Result of an f64-only operation; exact inputs come back as decimals
+    pub fn with_f64(&self, value: f64) -> Self {
+        match self {
+            // `sqrt(4)` is still a whole number, so there is no reason to leave fractions
+            Number::Rational(_) if value.fract() == 0.0 && value.abs() < MAX_EXACT_F64_INTEGER => {
+                Number::Rational(BigRational::from_integer(BigInt::from(value as i64)))
+            },
+            // Every digit f64 has, except that `sin(30°)` is 0.5 and not 0.49999999999999994
+            Number::Rational(_) | Number::Decimal(_) if value.is_finite() => {
+                let rounded = BigDecimal::from_str(&format!("{:.*e}", F64_SIGNIFICANT_DIGITS - 1, value)).map(|d| d.normalized());
+                let decimal = match rounded {
+                    Ok(rounded) if rounded.digits() <= CLEAN_F64_DIGITS => Ok(rounded),
+                    _ => BigDecimal::from_str(&format!("{:e}", value)),
+                };
+                decimal.map_or(Number::Float(value), Number::Decimal)
+            },
+            _ => Number::Float(value),
+        }
+    }
+
+
+    pub fn map_f64(&self, f: impl Fn(f64) -> f64) -> Self {
+        self.with_f64(f(self.to_f64()))
+    }
+
+
+    pub fn abs(&self) -> Self {
+        match self {
+            Number::Rational(value) => Number::Rational(value.abs()),
+            Number::Decimal(value) => Number::Decimal(value.abs()),
+            Number::Float(value) => Number::Float(value.abs()),
+            Number::Complex(value) => Number::Float(value.norm()),
+        }
+    }
+
+
+    // -1, 0 or 1 in the same representation; a complex number keeps its direction
+    pub fn signum(&self) -> Self {
+        match self {
+            Number::Rational(value) => Number::Rational(value.signum()),
+            Number::Decimal(value) => Number::Decimal(value.signum()),
+            Number::Float(value) if *value == 0.0 => Number::Float(0.0),
+            Number::Float(value) => Number::Float(value.signum()),
+            Number::Complex(value) if value.is_zero() => self.clone(),
+            Number::Complex(value) => Number::Complex(value / value.norm()),
+        }
+    }
+
+
+    // Roots of exact numbers are worked out in decimals, so `sqrt(2)^2` is 2; perfect squares stay fractions.
+    // Negative and f64 arguments are left to the f64 and complex path
+    pub fn sqrt(&self) -> Option<Self> {
+        match self {
+            Number::Rational(value) if !value.is_negative() => {
+                let (numer, denom) = (value.numer().sqrt(), value.denom().sqrt());
+                if &numer * &numer == *value.numer() && &denom * &denom == *value.denom() {
+                    Some(Number::Rational(BigRational::new(numer, denom)))
+                } else {
+                    rational_to_decimal(value).sqrt().map(Number::Decimal)
+                }
+            },
+            Number::Decimal(value) => value.sqrt().map(Number::Decimal),
+            _ => None,
+        }
+    }
+
+
+    pub fn cbrt(&self) -> Option<Self> {
+        match self {
+            Number::Rational(value) => {
+                let (numer, denom) = (value.numer().cbrt(), value.denom().cbrt());
+                if &numer * &numer * &numer == *value.numer() && &denom * &denom * &denom == *value.denom() {
+                    Some(Number::Rational(BigRational::new(numer, denom)))
+                } else {
+                    Some(Number::Decimal(rational_to_decimal(value).cbrt()))
+                }
+            },
+            Number::Decimal(value) => Some(Number::Decimal(value.cbrt())),
+            _ => None,
+        }
+    }
+
+
+    pub fn is_zero(&self) -> bool {
+        match self {
+            Number::Rational(value) => value.is_zero(),
+            Number::Decimal(value) => value.is_zero(),
+            Number::Float(value) => *value == 0.0,
+            Number::Complex(value) => value.is_zero(),
+        }
+    }
+
+
+    pub fn is_finite(&self) -> bool {
+        match self {
+            Number::Float(value) => value.is_finite(),
+            Number::Complex(value) => value.is_finite(),
+            _ => true,
+        }
+    }
+
+
+    fn is_complex(&self) -> bool {
+        matches!(self, Number::Complex(_))
+    }
+
+
+    fn to_integer_exponent(&self) -> Option<i64> {
+        match self {
+            Number::Rational(value) if value.is_integer() => value.to_integer().to_i64(),
+            Number::Decimal(value) if value.is_integer() => value.to_i64(),
+            _ => None,
+        }
+    }
+
+
+    // Digits an exact `self^n` runs to; decimals with a fraction part are cut to `DECIMAL_PRECISION` anyway
+    fn exact_power_digits(&self, n: i64) -> f64 {
+        let digits = match self {
+            Number::Rational(value) => integer_digits(value.numer()) + integer_digits(value.denom()),
+            Number::Decimal(value) if value.is_integer() => integer_digits(&value.as_bigint_and_exponent().0),
+            _ => 0.0,
+        };
+        digits * n.unsigned_abs() as f64
+    }
+
+
+    pub fn checked_div(self, divisor: Number) -> Result<Self, CalcError> {
+        if divisor.is_zero() {
+            return Err(ErrorKind::DivisionByZero.into());
+        }
+
+        Ok(match (self, divisor) {
+            (Number::Rational(a), Number::Rational(b)) => Number::Rational(a / b),
+            (a, b) if a.is_complex() || b.is_complex() => complex_result(a.to_complex() / b.to_complex(), &a, &b),
+            (a, b) => match (a.to_decimal(), b.to_decimal()) {
+                (Some(a), Some(b)) => Number::Decimal(a / b),
+                _ => Number::Float(a.to_f64() / b.to_f64()),
+            },
+        })
+    }
+
+
+    // `//` and `mod` round the quotient toward -inf, so `mod` takes the divisor's sign: `-7 mod 3` is 2
+    pub fn floor_div(self, divisor: Number) -> Result<Self, CalcError> {
+        self.checked_div(divisor)?.round_toward(RoundingMode::Floor)
+    }
+
+
+    pub fn modulo(self, divisor: Number) -> Result<Self, CalcError> {
+        let quotient = self.clone().floor_div(divisor.clone())?;
+        Ok(self - divisor * quotient)
+    }
+
+
+    // `rem` truncates the quotient toward zero, so it takes the dividend's sign: `-7 rem 3` is -1
+    pub fn remainder(self, divisor: Number) -> Result<Self, CalcError> {
+        let quotient = self.clone().checked_div(divisor.clone())?.round_toward(RoundingMode::Down)?;
+        Ok(self - divisor * quotient)
+    }
+
+
+    // To a whole number: Floor, Ceiling, HalfUp (half away from zero) or Down
+    pub fn round_toward(self, mode: RoundingMode) -> Result<Self, CalcError> {
+        match self {
+            Number::Rational(value) => Ok(Number::Rational(match mode {
+                RoundingMode::Floor => value.floor(),
+                RoundingMode::Ceiling => value.ceil(),
+                RoundingMode::HalfUp => value.round(),
+                _ => value.trunc(),
+            })),
+            Number::Decimal(value) => Ok(Number::Decimal(value.with_scale_round(0, mode))),
+            Number::Float(value) => Ok(Number::Float(match mode {
+                RoundingMode::Floor => value.floor(),
+                RoundingMode::Ceiling => value.ceil(),
+                RoundingMode::HalfUp => value.round(),
+                _ => value.trunc(),
+            })),
+            Number::Complex(_) => Err(ErrorKind::ComplexIntegerDivision.into()),
+        }
+    }
+
+
+    pub fn pow(self, exponent: Number) -> Result<Self, CalcError> {
+        if let Some(n) = exponent.to_integer_exponent() {
+            let digits = self.exact_power_digits(n);
+            if digits > MAX_EXACT_DIGITS {
+                return Err(ErrorKind::TooManyDigits(digits as u64).into());
+            }
+
+            match &self {
+                Number::Rational(base) => {
+                    if base.is_zero() && n < 0 {
+                        return Err(ErrorKind::DivisionByZero.into());
+                    }
+                    return Ok(Number::Rational(Pow::pow(base, BigInt::from(n))));
+                },
+                Number::Decimal(base) => {
+                    let magnitude = Number::Decimal(decimal_powi(base, n.unsigned_abs()));
+                    return if n < 0 {
+                        Number::Decimal(BigDecimal::one()).checked_div(magnitude)
+                    } else {
+                        Ok(magnitude)
+                    };
+                },
+                Number::Complex(base) if i32::try_from(n).is_ok() => {
+                    if base.is_zero() && n < 0 {
+                        return Err(ErrorKind::DivisionByZero.into());
+                    }
+                    return Ok(complex_result(base.powi(n as i32), &self, &exponent));
+                },
+                Number::Complex(_) | Number::Float(_) => {},
+            }
+        }
+
+        // `2^0.5` and `27^(1/3)` are roots, and roots of exact numbers have an exact path
+        if let Some(root) = self.root_power(&exponent) {
+            return Ok(root);
+        }
+
+        // `(-4)^0.5` is `2i`, like `sqrt(-4)`
+        let real = self.to_f64().powf(exponent.to_f64());
+        if self.is_complex() || exponent.is_complex() || (real.is_nan() && self.to_f64() < 0.0) {
+            if self.is_zero() {
+                return Ok(self);
+            }
+            return Ok(complex_result(self.to_complex().powc(exponent.to_complex()), &self, &exponent));
+        }
+
+        Ok(self.with_f64(real))
+    }
+}
+
+
+
+impl Add for Number {
+    type Output = Number;
+
+    fn add(self, other: Number) -> Number {
+        match (self, other) {
+            (Number::Rational(a), Number::Rational(b)) => Number::Rational(a + b),
+            (a, b) if a.is_complex() || b.is_complex() => complex_result(a.to_complex() + b.to_complex(), &a, &b),
+            (a, b) => match (a.to_decimal(), b.to_decimal()) {
+                (Some(a), Some(b)) => Number::Decimal(a + b),
+                _ => Number::Float(a.to_f64() + b.to_f64()),
+            },
+        }
+    }
+}
+
+
+
+impl Sub for Number {
+    type Output = Number;
+
+    fn sub(self, other: Number) -> Number {
+        match (self, other) {
+            (Number::Rational(a), Number::Rational(b)) => Number::Rational(a - b),
+            (a, b) if a.is_complex() || b.is_complex() => complex_result(a.to_complex() - b.to_complex(), &a, &b),
+            (a, b) => match (a.to_decimal(), b.to_decimal()) {
+                (Some(a), Some(b)) => Number::Decimal(a - b),
+                _ => Number::Float(a.to_f64() - b.to_f64()),
+            },
+        }
+    }
+}
+
+
+
+impl Mul for Number {
+    type Output = Number;
+
+    fn mul(self, other: Number) -> Number {
+        match (self, other) {
+            (Number::Rational(a), Number::Rational(b)) => Number::Rational(a * b),
+            (a, b) if a.is_complex() || b.is_complex() => complex_result(a.to_complex() * b.to_complex(), &a, &b),
+            (a, b) => match (a.to_decimal(), b.to_decimal()) {
+                (Some(a), Some(b)) => Number::Decimal(a * b),
+                _ => Number::Float(a.to_f64() * b.to_f64()),
+            },
+        }
+    }
+}
+
+
+
+impl Neg for Number {
+    type Output = Number;
+
+    fn neg(self) -> Number {
+        match self {
+            Number::Rational(value) => Number::Rational(-value),
+            Number::Decimal(value) => Number::Decimal(-value),
+            Number::Float(value) => Number::Float(-value),
+            Number::Complex(value) => Number::Complex(-value),
+        }
+    }
+}
+
+
+
+impl Number {
+    fn root_power(&self, exponent: &Number) -> Option<Number> {
+        let exponent = match exponent {
+            Number::Rational(value) => value.clone(),
+            Number::Decimal(value) => decimal_to_rational(value),
+            _ => return None,
+        };
+        if !matches!(self, Number::Rational(_) | Number::Decimal(_)) || self.to_f64() < 0.0 {
+            return None;
+        }
+
+        let root = match exponent.denom().to_u8() {
+            Some(2) => self.sqrt()?,
+            Some(3) => self.cbrt()?,
+            _ => return None,
+        };
+        root.pow(Number::from_integer(exponent.numer().clone())).ok()
+    }
+}
+
+
+
+fn complex_result(value: Complex64, a: &Number, b: &Number) -> Number {
+    let like = if a.is_complex() { b } else { a };
+    Number::from_complex(value, like)
+}
+
+
+
+// Integer bases stay exact, everything else is rounded to `DECIMAL_PRECISION`
+fn decimal_powi(base: &BigDecimal, mut exponent: u64) -> BigDecimal {
+    let limit = |value: BigDecimal| if base.is_integer() { value } else { value.with_prec(DECIMAL_PRECISION) };
+    let mut result = BigDecimal::one();
+    let mut square = base.clone();
+
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = limit(&result * &square);
+        }
+        exponent >>= 1;
+        if exponent > 0 {
+            square = limit(square.square());
+        }
+    }
+
+    result
+}
+
+
+
+// Decimal digits of a whole number, `log10` of it for short ones
+fn integer_digits(value: &BigInt) -> f64 {
+    match value.to_f64().map(f64::abs) {
+        Some(x) if x.is_finite() => x.log10().max(0.0),
+        _ => value.bits() as f64 * std::f64::consts::LOG10_2,
+    }
+}
+
+
+
+fn decimal_to_rational(value: &BigDecimal) -> BigRational {
+    let (digits, scale) = value.as_bigint_and_exponent();
+    let power_of_ten: BigInt = Pow::pow(BigInt::from(10), scale.unsigned_abs());
+
+    if scale >= 0 {
+        BigRational::new(digits, power_of_ten)
+    } else {
+        BigRational::from_integer(digits * power_of_ten)
+    }
+}
+
+
+
+pub fn rational_to_decimal(value: &BigRational) -> BigDecimal {
+    BigDecimal::from(value.numer().clone()) / BigDecimal::from(value.denom().clone())
+}
+
+
+
+// `-7/3` as (2, 1/3); the caller puts the sign back
+pub fn split_mixed(value: &BigRational) -> (BigInt, BigRational) {
+    let magnitude = value.abs();
+    let whole = magnitude.to_integer();
+    let fraction = magnitude - BigRational::from_integer(whole.clone());
+    (whole, fraction)
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    fn precise(literal: &str) -> Number {
+        Number::parse(literal, NumberMode::Precise).unwrap()
+    }
+
+
+    fn exact(literal: &str) -> Number {
+        Number::parse(literal, NumberMode::Exact).unwrap()
+    }
+
+
+    fn integer(literal: &str) -> Number {
+        Number::from_integer(literal.parse().unwrap())
+    }
+
+
+    #[test]
+    fn integer_powers_stay_exact_within_the_digit_budget() {
+        let power = precise("2").pow(precise("10001")).unwrap();
+        assert_eq!(power.to_integer(), Some(Pow::pow(BigInt::from(2), 10001u32)));
+        assert_eq!(precise("1").pow(precise("99999999999")).unwrap(), precise("1"));
+        assert_eq!(precise("2").pow(precise("2000000")).unwrap_err().code(), "too-many-digits");
+    }
+
+
+    #[test]
+    fn exact_fractions_add_without_rounding() {
+        let third = exact("1").checked_div(exact("3")).unwrap();
+        let sixth = exact("1").checked_div(exact("6")).unwrap();
+        assert_eq!(third + sixth, exact("1").checked_div(exact("2")).unwrap());
+    }
+
+
+    #[test]
+    fn mod_takes_the_divisor_sign_and_rem_the_dividend_sign() {
+        // dividend, divisor, //, mod, rem
+        let cases = [("7", "3", "2", "1", "1"), ("-7", "3", "-3", "2", "-1"), ("7", "-3", "-3", "-2", "1"), ("-7", "-3", "2", "-1", "-1")];
+        for (a, b, quotient, modulo, remainder) in cases {
+            let (a, b) = (integer(a), integer(b));
+            assert_eq!(a.clone().floor_div(b.clone()).unwrap(), integer(quotient), "{:?} // {:?}", a, b);
+            assert_eq!(a.clone().modulo(b.clone()).unwrap(), integer(modulo), "{:?} mod {:?}", a, b);
+            assert_eq!(a.clone().remainder(b.clone()).unwrap(), integer(remainder), "{:?} rem {:?}", a, b);
+        }
+        assert_eq!(precise("7.5").modulo(integer("-2")).unwrap(), precise("0") - precise("0.5"));
+        assert_eq!(integer("1").modulo(integer("0")).unwrap_err().code(), "division-by-zero");
+    }
+}
@@ -0,0 +1,695 @@
+use crate::constants::find_constant;
+use crate::error::{CalcError, ErrorKind, Span};
+use crate::functions::{AngleMode, find_function};
+use crate::programmer::{BitOp, radix_of_prefix};
+use num_bigint::BigInt;
+
+
+// `mod`/`rem` are written as words, so the parser has to tell them apart from names
+pub const WORD_OPERATORS: &[&str] = &["mod", "rem"];
+// Programmer mode adds its own; `xor`/`ror` come before `or` so the longer word wins
+pub const PROGRAMMER_WORD_OPERATORS: &[&str] = &["mod", "rem", "xor", "ror", "rol", "and", "or", "not"];
+// Loosest first, all below `+`/`-`
+const BITWISE_LEVELS: &[&[(&str, BitOp)]] = &[
+    &[("or", BitOp::Or)],
+    &[("xor", BitOp::Xor)],
+    &[("and", BitOp::And)],
+    &[("<<", BitOp::ShiftLeft), (">>", BitOp::ShiftRight), ("rol", BitOp::RotateLeft), ("ror", BitOp::RotateRight)],
+];
+
+
+
+// How `1/2x` reads: a juxtaposed product binds tighter than `÷`, or is an ordinary `×`
+#[derive(Clone, Copy, PartialEq)]
+pub enum ImplicitMultiplication {
+    Tight,
+    LeftToRight,
+}
+
+
+
+// What a user-defined name stands for; built-in names are known to the parser itself
+#[derive(Clone, Copy, PartialEq)]
+pub enum NameKind {
+    Value,
+    Function,
+}
+
+
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow,
+    Mod,
+    Rem,
+    FloorDiv,
+    Bit(BitOp),
+    // `200+10%`: the right side is a share of the left one
+    PercentAdd,
+    PercentSub,
+}
+
+
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum PostfixOp {
+    Factorial,
+    Percent,
+    Imaginary,
+    Angle(AngleMode),
+}
+
+
+
+#[derive(Clone)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+
+
+#[derive(Clone)]
+pub enum ExprKind {
+    // Literal text, so it is read in whatever number mode is active at evaluation time
+    Number(String),
+    Name(String),
+    Negate(Box<Expr>),
+    // `not x`
+    BitNot(Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Postfix(PostfixOp, Box<Expr>),
+    Call(String, Vec<Expr>),
+    // `ans` is 1, `ans2` the result before it
+    Answer(usize),
+    // `#3`, counted from the first result of the session
+    Entry(usize),
+}
+
+
+
+impl ImplicitMultiplication {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ImplicitMultiplication::Tight => "1/(2x)",
+            ImplicitMultiplication::LeftToRight => "(1/2)x",
+        }
+    }
+
+
+    pub fn next(&self) -> Self {
+        match self {
+            ImplicitMultiplication::Tight => ImplicitMultiplication::LeftToRight,
+            ImplicitMultiplication::LeftToRight => ImplicitMultiplication::Tight,
+        }
+    }
+}
+
+
+
+pub struct Parser<'a> {
+    // `×`/`÷` are read as `*`/`/` and spaces are dropped; `offsets` maps each byte back into the original text
+    input: Vec<u8>,
+    offsets: Vec<usize>,
+    pos: usize,
+    implicit_multiplication: ImplicitMultiplication,
+    // Radix literals and bitwise operators
+    programmer: bool,
+    lookup: &'a dyn Fn(&str) -> Option<NameKind>,
+}
+
+
+
+impl<'a> Parser<'a> {
+    pub fn new(text: &str, implicit_multiplication: ImplicitMultiplication, programmer: bool,
+               lookup: &'a dyn Fn(&str) -> Option<NameKind>) -> Self {
+        let mut input = Vec::new();
+        let mut offsets = Vec::new();
+        for (offset, c) in text.char_indices() {
+            let c = match c {
+                '×' => '*',
+                '÷' => '/',
+                c if c.is_whitespace() => continue,
+                c => c,
+            };
+            let mut buffer = [0; 4];
+            for &byte in c.encode_utf8(&mut buffer).as_bytes() {
+                input.push(byte);
+                offsets.push(offset);
+            }
+        }
+        offsets.push(text.len());
+
+        Self {
+            input,
+            offsets,
+            pos: 0,
+            implicit_multiplication,
+            programmer,
+            lookup,
+        }
+    }
+
+
+    pub fn parse(&mut self) -> Result<Expr, CalcError> {
+        let tree = self.parse_expression()?;
+        if self.pos < self.input.len() {
+            return Err(self.unexpected_character());
+        }
+        Ok(tree)
+    }
+
+
+    fn parse_expression(&mut self) -> Result<Expr, CalcError> {
+        self.parse_bitwise(0)
+    }
+
+
+    fn parse_bitwise(&mut self, level: usize) -> Result<Expr, CalcError> {
+        if !self.programmer || level == BITWISE_LEVELS.len() {
+            return self.parse_sum();
+        }
+
+        let mut tree = self.parse_bitwise(level + 1)?;
+        while let Some((token, op)) = self.bitwise_operator_at(level) {
+            self.pos += token.len();
+            tree = binary(BinaryOp::Bit(op), tree, self.parse_bitwise(level + 1)?);
+        }
+        Ok(tree)
+    }
+
+
+    fn bitwise_operator_at(&self, level: usize) -> Option<(&'static str, BitOp)> {
+        let word = self.word_operator_at(self.pos);
+        BITWISE_LEVELS[level].iter().copied().find(|&(token, _)| {
+            if token.starts_with(|c: char| c.is_ascii_alphabetic()) {
+                word == Some(token)
+            } else {
+                self.input[self.pos..].starts_with(token.as_bytes())
+            }
+        })
+    }
+
+
+    fn parse_sum(&mut self) -> Result<Expr, CalcError> {
+        let mut tree = self.parse_term()?;
+
+        while self.pos < self.input.len() {
+            let op = match self.current_char() {
+                b'+' => BinaryOp::Add,
+                b'-' => BinaryOp::Sub,
+                _ => break,
+            };
+            self.pos += 1;
+            let term = self.parse_term()?;
+
+            // A percent right after `+`/`-` is taken of the left side: `200+10%` is 220
+            let op = match op {
+                BinaryOp::Add if self.ends_with_percent() => BinaryOp::PercentAdd,
+                BinaryOp::Sub if self.ends_with_percent() => BinaryOp::PercentSub,
+                op => op,
+            };
+            tree = binary(op, tree, term);
+        }
+
+        Ok(tree)
+    }
+
+
+    fn parse_term(&mut self) -> Result<Expr, CalcError> {
+        let mut tree = self.parse_product()?;
+
+        while self.pos < self.input.len() {
+            if let Some(word) = self.word_operator_at(self.pos) {
+                let op = match word {
+                    "mod" => BinaryOp::Mod,
+                    "rem" => BinaryOp::Rem,
+                    _ => break,
+                };
+                self.pos += word.len();
+                tree = binary(op, tree, self.parse_product()?);
+                continue;
+            }
+
+            let op = match self.current_char() {
+                b'*' => BinaryOp::Mul,
+                b'/' if self.next_char() == b'/' => BinaryOp::FloorDiv,
+                b'/' => BinaryOp::Div,
+                b'%' if self.next_char() == b'%' => BinaryOp::Rem,
+                _ if self.starts_operand() && self.implicit_multiplication == ImplicitMultiplication::LeftToRight => {
+                    tree = binary(BinaryOp::Mul, tree, self.parse_power()?);
+                    continue;
+                },
+                _ => break,
+            };
+            self.pos += if matches!(op, BinaryOp::FloorDiv | BinaryOp::Rem) { 2 } else { 1 };
+            tree = binary(op, tree, self.parse_product()?);
+        }
+
+        Ok(tree)
+    }
+
+
+    // Juxtaposed operands, `2x` or `(1+2)(3+4)`, when they bind tighter than `×`/`÷`
+    fn parse_product(&mut self) -> Result<Expr, CalcError> {
+        let mut tree = self.parse_factor()?;
+
+        if self.implicit_multiplication == ImplicitMultiplication::Tight {
+            while self.starts_operand() {
+                tree = binary(BinaryOp::Mul, tree, self.parse_power()?);
+            }
+        }
+
+        Ok(tree)
+    }
+
+
+    fn starts_operand(&self) -> bool {
+        let c = self.current_char();
+        (c.is_ascii_digit() || c == b'.' || c == b'(' || c.is_ascii_alphabetic()) && self.word_operator_at(self.pos).is_none()
+    }
+
+
+    // `mod`/`rem` where an operator can go, unless they begin a name that is actually defined (`2model`)
+    fn word_operator_at(&self, pos: usize) -> Option<&'static str> {
+        let name = self.name_at(pos);
+        if self.is_known_name(name) {
+            return None;
+        }
+        self.word_operators().iter().copied().find(|word| name.starts_with(word))
+    }
+
+
+    fn word_operators(&self) -> &'static [&'static str] {
+        if self.programmer { PROGRAMMER_WORD_OPERATORS } else { WORD_OPERATORS }
+    }
+
+
+    fn name_at(&self, pos: usize) -> &str {
+        let input = &self.input;
+        let end = (pos..input.len()).find(|&i| !is_identifier_char(input[i] as char)).unwrap_or(input.len());
+        std::str::from_utf8(&input[pos..end]).unwrap_or("")
+    }
+
+
+    fn name_kind(&self, name: &str) -> Option<NameKind> {
+        if name == "if" || find_function(name).is_some() {
+            return Some(NameKind::Function);
+        }
+        if name == "i" || answer_index(name).is_some() {
+            return Some(NameKind::Value);
+        }
+        // User names shadow constants, the same way they do at evaluation
+        (self.lookup)(name).or(find_constant(name).map(|_| NameKind::Value))
+    }
+
+
+    fn is_known_name(&self, name: &str) -> bool {
+        self.name_kind(name).is_some()
+    }
+
+
+    // Unary signs bind looser than `^`, so `-2^2` is `-(2^2)`
+    fn parse_factor(&mut self) -> Result<Expr, CalcError> {
+        if self.pos >= self.input.len() {
+            return Err(self.unexpected_end());
+        }
+
+        let start = self.pos;
+        match self.current_char() {
+            b'-' => {
+                self.pos += 1;
+                let operand = self.parse_factor()?;
+                Ok(self.node(ExprKind::Negate(Box::new(operand)), start))
+            },
+            b'+' => {
+                self.pos += 1;
+                self.parse_factor()
+            },
+            _ if self.programmer && self.word_operator_at(self.pos) == Some("not") => {
+                self.pos += "not".len();
+                let operand = self.parse_factor()?;
+                Ok(self.node(ExprKind::BitNot(Box::new(operand)), start))
+            },
+            _ => self.parse_power(),
+        }
+    }
+
+
+    // Right-associative: `2^3^2` is `2^(3^2)`
+    fn parse_power(&mut self) -> Result<Expr, CalcError> {
+        let base = self.parse_primary()?;
+
+        if self.pos < self.input.len() && self.current_char() == b'^' {
+            self.pos += 1;
+            let exponent = self.parse_factor()?;
+            return Ok(binary(BinaryOp::Pow, base, exponent));
+        }
+
+        Ok(base)
+    }
+
+
+    fn parse_primary(&mut self) -> Result<Expr, CalcError> {
+        if self.pos >= self.input.len() {
+            return Err(self.unexpected_end());
+        }
+
+        let start = self.pos;
+        let tree = match self.current_char() {
+            b'(' => {
+                self.pos += 1;
+                let tree = self.parse_expression()?;
+                self.expect_closing(start)?;
+                // The brackets belong to the node, so an error inside `(…)` underlines them too
+                Expr { kind: tree.kind, span: self.span_from(start) }
+            },
+            c if c.is_ascii_alphabetic() => self.parse_identifier()?,
+            c if c.is_ascii_digit() || c == b'.' => self.parse_number()?,
+            b'#' => self.parse_entry()?,
+            _ => return Err(self.unexpected_character()),
+        };
+
+        // Postfix `!` binds tighter than `^`: `2^3!` is `2^6`
+        let mut tree = self.parse_suffix(tree, start);
+        while self.current_char() == b'!' {
+            self.pos += 1;
+            tree = self.node(ExprKind::Postfix(PostfixOp::Factorial, Box::new(tree)), start);
+        }
+
+        if self.current_char() == b'%' && self.next_char() != b'%' {
+            self.pos += 1;
+            tree = self.node(ExprKind::Postfix(PostfixOp::Percent, Box::new(tree)), start);
+        }
+        Ok(tree)
+    }
+
+
+    fn ends_with_percent(&self) -> bool {
+        self.pos > 0 && self.input[self.pos - 1] == b'%'
+    }
+
+
+    // `4i` is an imaginary literal; `30°`, `1.2rad`, `50grad` carry their own angle unit
+    fn parse_suffix(&mut self, tree: Expr, operand_start: usize) -> Expr {
+        let start = self.pos;
+        let degree_sign = "°".as_bytes();
+
+        let suffix = if self.input[self.pos..].starts_with(degree_sign) {
+            self.pos += degree_sign.len();
+            "°"
+        } else {
+            while self.pos < self.input.len() && self.current_char().is_ascii_alphabetic() {
+                self.pos += 1;
+            }
+            std::str::from_utf8(&self.input[start..self.pos]).unwrap_or("")
+        };
+
+        if suffix == "i" {
+            return self.node(ExprKind::Postfix(PostfixOp::Imaginary, Box::new(tree)), operand_start);
+        }
+
+        match AngleMode::from_suffix(suffix) {
+            Some(unit) => self.node(ExprKind::Postfix(PostfixOp::Angle(unit), Box::new(tree)), operand_start),
+            None => {
+                self.pos = start;
+                tree
+            }
+        }
+    }
+
+
+    fn parse_identifier(&mut self) -> Result<Expr, CalcError> {
+        let start = self.pos;
+        let mut name = self.name_at(self.pos).to_string();
+
+        // `xmody` is `x mod y` unless there really is a name like that; `theorem` has no `theo` to take the remainder of
+        if !self.is_known_name(&name) &&
+           let Some(split) = (1..name.len()).find(|&k| self.name_kind(&name[..k]) == Some(NameKind::Value) &&
+                                                      self.word_operators().iter().any(|word| name[k..].starts_with(word))) {
+            name.truncate(split);
+        }
+        self.pos += name.len();
+        if let Some(back) = answer_index(&name) {
+            return Ok(self.node(ExprKind::Answer(back), start));
+        }
+
+        // A value followed by a bracket is a product: `x(2)`, `pi(1+1)`
+        let kind = self.name_kind(&name);
+        if self.current_char() != b'(' || kind == Some(NameKind::Value) {
+            if kind == Some(NameKind::Function) {
+                return Err(CalcError::at(ErrorKind::MissingArguments(name), self.span_from(start)));
+            }
+            return Ok(self.node(ExprKind::Name(name), start));
+        }
+
+        let open = self.pos;
+        self.pos += 1;
+        let arguments = self.parse_arguments(open)?;
+        Ok(self.node(ExprKind::Call(name, arguments), start))
+    }
+
+
+    fn parse_entry(&mut self) -> Result<Expr, CalcError> {
+        let start = self.pos;
+        self.pos += 1;
+        while self.current_char().is_ascii_digit() {
+            self.pos += 1;
+        }
+        if self.pos == start + 1 {
+            return Err(if self.pos >= self.input.len() { self.unexpected_end() } else { self.unexpected_character() });
+        }
+
+        let digits = String::from_utf8_lossy(&self.input[start + 1..self.pos]).into_owned();
+        let index = digits.parse().map_err(|_| CalcError::at(ErrorKind::InvalidNumber, self.span_from(start)))?;
+        Ok(self.node(ExprKind::Entry(index), start))
+    }
+
+
+    // Comma-separated call arguments up to and including the closing parenthesis
+    fn parse_arguments(&mut self, open: usize) -> Result<Vec<Expr>, CalcError> {
+        let mut arguments = Vec::new();
+        if self.current_char() == b')' {
+            self.pos += 1;
+            return Ok(arguments);
+        }
+
+        loop {
+            arguments.push(self.parse_expression()?);
+            match self.current_char() {
+                b',' => self.pos += 1,
+                _ => {
+                    self.expect_closing(open)?;
+                    return Ok(arguments);
+                },
+            }
+        }
+    }
+
+
+    // The error points at the bracket that was left open
+    fn expect_closing(&mut self, open: usize) -> Result<(), CalcError> {
+        if self.current_char() != b')' {
+            return Err(CalcError::at(ErrorKind::MissingClosingParenthesis, self.span_between(open, open + 1)));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+
+    fn parse_number(&mut self) -> Result<Expr, CalcError> {
+        let start = self.pos;
+        if self.programmer && self.current_char() == b'0' &&
+           let Some(radix) = radix_of_prefix(self.next_char()) {
+            return self.parse_radix_literal(radix);
+        }
+
+        let has_digits = self.pos < self.input.len() && self.current_char().is_ascii_digit();
+        while self.pos < self.input.len() && self.current_char().is_ascii_digit() {
+            self.pos += 1;
+        }
+
+        let has_decimal = if self.pos < self.input.len() && self.current_char() == b'.' {
+            self.pos += 1;
+            while self.pos < self.input.len() && self.current_char().is_ascii_digit() {
+                self.pos += 1;
+            }
+            true
+        } else {
+            false
+        };
+
+        let has_exponent = if self.pos < self.input.len() && (self.current_char() == b'e' || self.current_char() == b'E') {
+            let mantissa_end = self.pos;
+            self.pos += 1;
+            if self.pos < self.input.len() && (self.current_char() == b'+' || self.current_char() == b'-') {
+                self.pos += 1;
+            }
+            let exp_start = self.pos;
+            while self.pos < self.input.len() && self.current_char().is_ascii_digit() {
+                self.pos += 1;
+            }
+            // No exponent digits: `2e` is two times e
+            if exp_start == self.pos {
+                self.pos = mantissa_end;
+                false
+            } else {
+                true
+            }
+        } else {
+            false
+        };
+
+        if !has_digits && !has_exponent && has_decimal {
+            return Err(CalcError::at(ErrorKind::InvalidNumber, self.span_from(start)));
+        }
+
+        let literal = String::from_utf8_lossy(&self.input[start..self.pos]).into_owned();
+        Ok(self.node(ExprKind::Number(literal), start))
+    }
+
+
+    // `0xff`, `0b1010`, `0o17`; kept as decimal text like any other literal
+    fn parse_radix_literal(&mut self, radix: u32) -> Result<Expr, CalcError> {
+        let start = self.pos;
+        self.pos += 2;
+        // `0xffand3`: the `a` of `and` is not a digit
+        while (self.current_char() as char).is_digit(radix) && self.word_operator_at(self.pos).is_none() {
+            self.pos += 1;
+        }
+        // `0x` with the digits still to come
+        if self.pos == start + 2 && self.pos == self.input.len() {
+            return Err(self.unexpected_end());
+        }
+
+        let value = BigInt::parse_bytes(&self.input[start + 2..self.pos], radix)
+            .ok_or_else(|| CalcError::at(ErrorKind::InvalidNumber, self.span_from(start)))?;
+        Ok(self.node(ExprKind::Number(value.to_string()), start))
+    }
+
+
+    fn node(&self, kind: ExprKind, start: usize) -> Expr {
+        Expr { kind, span: self.span_from(start) }
+    }
+
+
+    fn span_from(&self, start: usize) -> Span {
+        self.span_between(start, self.pos)
+    }
+
+
+    fn span_between(&self, start: usize, end: usize) -> Span {
+        Span { start: self.offsets[start], end: self.offsets[end] }
+    }
+
+
+    fn unexpected_character(&self) -> CalcError {
+        // Whole character, so a stray `°` is underlined rather than half of it
+        let end = (self.pos + 1..self.input.len()).find(|&i| self.offsets[i] != self.offsets[self.pos]).unwrap_or(self.input.len());
+        CalcError::at(ErrorKind::UnexpectedCharacter, self.span_between(self.pos, end))
+    }
+
+
+    fn unexpected_end(&self) -> CalcError {
+        CalcError::at(ErrorKind::UnexpectedEnd, self.span_between(self.input.len(), self.input.len()))
+    }
+
+
+    fn next_char(&self) -> u8 {
+        self.input.get(self.pos + 1).copied().unwrap_or(0)
+    }
+
+
+    fn current_char(&self) -> u8 {
+        if self.pos < self.input.len() {
+            self.input[self.pos]
+        } else {
+            0
+        }
+    }
+}
+
+
+
+fn binary(op: BinaryOp, lhs: Expr, rhs: Expr) -> Expr {
+    let span = Span { start: lhs.span.start, end: rhs.span.end };
+    Expr { kind: ExprKind::Binary(op, Box::new(lhs), Box::new(rhs)), span }
+}
+
+
+
+// `ans` -> 1, `ans3` -> 3; `ans0` and `ans01` are ordinary names
+pub fn answer_index(name: &str) -> Option<usize> {
+    match name.strip_prefix("ans")? {
+        "" => Some(1),
+        digits if !digits.starts_with('0') && digits.bytes().all(|b| b.is_ascii_digit()) => digits.parse().ok(),
+        _ => None,
+    }
+}
+
+
+
+// `N_A`, `log10`
+pub fn is_identifier_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    // The tree written back with every binary operation in brackets; `x` and `y` are variables
+    fn shape(text: &str) -> String {
+        let lookup = |name: &str| ["x", "y"].contains(&name).then_some(NameKind::Value);
+        let tree = Parser::new(text, ImplicitMultiplication::Tight, false, &lookup).parse().unwrap();
+        render(&tree)
+    }
+
+
+    fn render(tree: &Expr) -> String {
+        match &tree.kind {
+            ExprKind::Number(text) | ExprKind::Name(text) => text.clone(),
+            ExprKind::Negate(operand) => format!("-{}", render(operand)),
+            ExprKind::Binary(op, lhs, rhs) => {
+                let symbol = match op {
+                    BinaryOp::Add => "+",
+                    BinaryOp::Sub => "-",
+                    BinaryOp::Mul => "×",
+                    BinaryOp::Div => "÷",
+                    BinaryOp::Pow => "^",
+                    BinaryOp::Mod => " mod ",
+                    BinaryOp::Rem => " rem ",
+                    BinaryOp::FloorDiv => " // ",
+                    _ => "?",
+                };
+                format!("({}{}{})", render(lhs), symbol, render(rhs))
+            },
+            _ => "?".to_string(),
+        }
+    }
+
+
+    #[test]
+    fn powers_group_from_the_right_and_above_signs() {
+        assert_eq!(shape("2^3^2"), "(2^(3^2))");
+        assert_eq!(shape("-2^2"), "-(2^2)");
+        assert_eq!(shape("2^-1"), "(2^-1)");
+        assert_eq!(shape("2×3^2"), "(2×(3^2))");
+    }
+
+
+    #[test]
+    fn operator_words_end_names_only_after_a_known_one() {
+        assert_eq!(shape("7mod-3"), "(7 mod -3)");
+        assert_eq!(shape("xmody"), "(x mod y)");
+        assert_eq!(shape("-7//2rem3"), "((-7 // 2) rem 3)");
+        assert_eq!(shape("theorem"), "theorem");
+    }
+}
@@ -0,0 +1,263 @@
+use num_bigint::BigInt;
+use num_traits::{One, Signed, ToPrimitive};
+
+use crate::error::{CalcError, ErrorKind};
+
+
+// Shifting further only builds enormous numbers
+const MAX_SHIFT: u32 = 4096;
+// Hex digits per group in the word display
+const GROUP_DIGITS: usize = 4;
+// Bases `to base N` accepts; digits run out at `z`
+pub const MIN_RADIX: u32 = 2;
+pub const MAX_RADIX: u32 = 36;
+const SUBSCRIPT_DIGITS: [char; 10] = ['₀', '₁', '₂', '₃', '₄', '₅', '₆', '₇', '₈', '₉'];
+
+
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum WordSize {
+    Bits8,
+    Bits16,
+    Bits32,
+    Bits64,
+    Bits128,
+}
+
+
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Signedness {
+    Signed,
+    Unsigned,
+}
+
+
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum BitOp {
+    And,
+    Or,
+    Xor,
+    ShiftLeft,
+    ShiftRight,
+    RotateLeft,
+    RotateRight,
+}
+
+
+
+impl WordSize {
+    pub fn bits(&self) -> u32 {
+        match self {
+            WordSize::Bits8 => 8,
+            WordSize::Bits16 => 16,
+            WordSize::Bits32 => 32,
+            WordSize::Bits64 => 64,
+            WordSize::Bits128 => 128,
+        }
+    }
+
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WordSize::Bits8 => "8-bit",
+            WordSize::Bits16 => "16-bit",
+            WordSize::Bits32 => "32-bit",
+            WordSize::Bits64 => "64-bit",
+            WordSize::Bits128 => "128-bit",
+        }
+    }
+
+
+    pub fn next(&self) -> Self {
+        match self {
+            WordSize::Bits8 => WordSize::Bits16,
+            WordSize::Bits16 => WordSize::Bits32,
+            WordSize::Bits32 => WordSize::Bits64,
+            WordSize::Bits64 => WordSize::Bits128,
+            WordSize::Bits128 => WordSize::Bits8,
+        }
+    }
+
+
+    fn mask(&self) -> BigInt {
+        (BigInt::one() << self.bits()) - 1
+    }
+}
+
+
+
+impl Signedness {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Signedness::Signed => "Signed",
+            Signedness::Unsigned => "Unsigned",
+        }
+    }
+
+
+    pub fn next(&self) -> Self {
+        match self {
+            Signedness::Signed => Signedness::Unsigned,
+            Signedness::Unsigned => Signedness::Signed,
+        }
+    }
+}
+
+
+
+// Negative numbers act as two's complement with endless sign bits, so `-1 and 0xff` is 255
+pub fn apply(op: BitOp, lhs: &BigInt, rhs: &BigInt, size: WordSize) -> Result<BigInt, CalcError> {
+    Ok(match op {
+        BitOp::And => lhs & rhs,
+        BitOp::Or => lhs | rhs,
+        BitOp::Xor => lhs ^ rhs,
+        BitOp::ShiftLeft => lhs << shift_count(rhs)?,
+        BitOp::ShiftRight => lhs >> shift_count(rhs)?,
+        BitOp::RotateLeft => rotate_left(lhs, rhs, size),
+        BitOp::RotateRight => rotate_left(lhs, &-rhs, size),
+    })
+}
+
+
+
+// What a register of `size` holds after `value` is stored in it, and whether anything was lost
+pub fn wrap(value: &BigInt, size: WordSize, signedness: Signedness) -> (BigInt, bool) {
+    let word = to_word(value, size);
+    let wrapped = match signedness {
+        Signedness::Signed if word >= BigInt::one() << (size.bits() - 1) => word - (BigInt::one() << size.bits()),
+        _ => word,
+    };
+    let overflowed = &wrapped != value;
+    (wrapped, overflowed)
+}
+
+
+
+// The bits of the register in hex, so `-1` in 16 bits reads `FFFF`
+pub fn format_word(value: &BigInt, size: WordSize) -> String {
+    let digits = size.bits() as usize / 4;
+    let hex = format!("{:0>width$}", to_word(value, size).to_str_radix(16).to_uppercase(), width = digits);
+    hex.as_bytes()
+        .chunks(GROUP_DIGITS)
+        .map(|group| std::str::from_utf8(group).unwrap_or_default())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+
+
+pub fn not(value: &BigInt) -> BigInt {
+    !value
+}
+
+
+
+// `0x`, `0b`, `0o` after the leading zero
+pub fn radix_of_prefix(prefix: u8) -> Option<u32> {
+    match prefix.to_ascii_lowercase() {
+        b'x' => Some(16),
+        b'b' => Some(2),
+        b'o' => Some(8),
+        _ => None,
+    }
+}
+
+
+
+// `in hex`, `in bin`, ...
+pub fn radix_of_name(name: &str) -> Option<u32> {
+    match name {
+        "hex" => Some(16),
+        "bin" => Some(2),
+        "oct" => Some(8),
+        "dec" => Some(10),
+        _ => None,
+    }
+}
+
+
+
+pub fn to_radix_digits(value: &BigInt, radix: u32) -> String {
+    value.to_str_radix(radix).to_uppercase()
+}
+
+
+
+// Written the way it would be typed back in: `0xFF`, `-0b101`, and other bases with a subscript, `1Z₃₆`
+pub fn format_radix(value: &BigInt, radix: u32) -> String {
+    let sign = if value.is_negative() { "-" } else { "" };
+    let digits = to_radix_digits(&value.abs(), radix);
+    match radix {
+        16 => format!("{}0x{}", sign, digits),
+        2 => format!("{}0b{}", sign, digits),
+        8 => format!("{}0o{}", sign, digits),
+        10 => format!("{}{}", sign, digits),
+        _ => {
+            let subscript: String = radix.to_string().bytes().map(|b| SUBSCRIPT_DIGITS[(b - b'0') as usize]).collect();
+            format!("{}{}{}", sign, digits, subscript)
+        },
+    }
+}
+
+
+
+fn shift_count(count: &BigInt) -> Result<u32, CalcError> {
+    count
+        .to_u32()
+        .filter(|&n| n <= MAX_SHIFT)
+        .ok_or_else(|| ErrorKind::ShiftOutOfRange(count.to_string()).into())
+}
+
+
+
+// Two's complement bit pattern of the lowest `size` bits
+pub fn to_word(value: &BigInt, size: WordSize) -> BigInt {
+    value & size.mask()
+}
+
+
+
+// The value is cut to a word first; a negative count turns the other way
+fn rotate_left(value: &BigInt, count: &BigInt, size: WordSize) -> BigInt {
+    let bits = BigInt::from(size.bits());
+    let count = ((count % &bits) + &bits) % &bits;
+    let count = count.to_u32().unwrap_or(0);
+
+    let word = to_word(value, size);
+    ((&word << count) | (&word >> (size.bits() - count))) & size.mask()
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    fn int(value: i64) -> BigInt {
+        BigInt::from(value)
+    }
+
+
+    #[test]
+    fn rotations_stay_inside_the_word() {
+        assert_eq!(apply(BitOp::RotateLeft, &int(0x81), &int(1), WordSize::Bits8).unwrap(), int(0x03));
+        assert_eq!(apply(BitOp::RotateRight, &int(0x81), &int(1), WordSize::Bits8).unwrap(), int(0xC0));
+        assert_eq!(apply(BitOp::RotateLeft, &int(1), &int(17), WordSize::Bits16).unwrap(), int(2));
+        assert_eq!(apply(BitOp::ShiftLeft, &int(1), &int(5000), WordSize::Bits64).unwrap_err().code(), "shift-out-of-range");
+        assert_eq!(format_word(&int(-1), WordSize::Bits16), "FFFF");
+    }
+
+
+    #[test]
+    fn values_wrap_to_the_word_and_report_overflow() {
+        assert_eq!(wrap(&int(127), WordSize::Bits8, Signedness::Signed), (int(127), false));
+        assert_eq!(wrap(&int(128), WordSize::Bits8, Signedness::Signed), (int(-128), true));
+        assert_eq!(wrap(&int(-1), WordSize::Bits8, Signedness::Signed), (int(-1), false));
+        assert_eq!(wrap(&int(-1), WordSize::Bits8, Signedness::Unsigned), (int(255), true));
+        assert_eq!(wrap(&int(256), WordSize::Bits8, Signedness::Unsigned), (int(0), true));
+        assert_eq!(wrap(&int(i64::MAX).pow(2u32), WordSize::Bits64, Signedness::Signed), (int(1), true));
+    }
+}
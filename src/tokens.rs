@@ -0,0 +1,155 @@
+use std::fmt;
+
+use crate::parser::is_identifier_char;
+use crate::programmer::radix_of_prefix;
+
+
+const OPERATOR_SYMBOLS: &str = "+-×÷^";
+// Checked before the single characters, so `//` is not two divisions
+const LONG_SYMBOL_OPERATORS: &[&str] = &["//", "%%", "<<", ">>"];
+const ANSWER: &str = "ans";
+
+
+
+// One unit of the expression being typed; the input rules and backspace work on these instead of on characters
+#[derive(Clone, Debug, PartialEq)]
+pub enum Token {
+    // Digits as typed, with a decimal point, exponent or radix prefix: `12.`, `1.5e20`, `0xFF`
+    Number(String),
+    // Variables, constants, units and words still being typed: `x`, `pi`, `rad`, `ans2`
+    Name(String),
+    // `ans` from the Ans key, which digits do not extend
+    Answer,
+    // A name together with its bracket, so `sin(` goes away as a whole
+    Function(String),
+    // `+`, `×`, `mod`, `//`, `<<`, `not`
+    Operator(String),
+    OpenParen,
+    CloseParen,
+    Percent,
+    Factorial,
+    Degree,
+    Separator,
+    Assign,
+    HistoryReference,
+}
+
+
+
+impl Token {
+    // `0x` with no digits yet
+    pub fn is_radix_prefix(&self) -> bool {
+        matches!(self, Token::Number(digits) if digits.len() == 2 && digits.starts_with('0') &&
+                                               radix_of_prefix(digits.as_bytes()[1]).is_some())
+    }
+
+
+    // Radix of a `0x`/`0b`/`0o` literal; plain numbers have none
+    pub fn radix(&self) -> Option<u32> {
+        match self {
+            Token::Number(digits) if digits.len() >= 2 && digits.starts_with('0') => radix_of_prefix(digits.as_bytes()[1]),
+            _ => None,
+        }
+    }
+}
+
+
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Number(text) | Token::Name(text) | Token::Operator(text) => write!(f, "{}", text),
+            Token::Function(name) => write!(f, "{}(", name),
+            Token::Answer => write!(f, "ans"),
+            Token::OpenParen => write!(f, "("),
+            Token::CloseParen => write!(f, ")"),
+            Token::Percent => write!(f, "%"),
+            Token::Factorial => write!(f, "!"),
+            Token::Degree => write!(f, "°"),
+            Token::Separator => write!(f, ","),
+            Token::Assign => write!(f, "="),
+            Token::HistoryReference => write!(f, "#"),
+        }
+    }
+}
+
+
+
+pub fn to_text(tokens: &[Token]) -> String {
+    tokens.iter().map(Token::to_string).collect()
+}
+
+
+
+// Reads back text the calculator wrote itself, a result to carry on from or a balanced expression;
+// `word_operators` are the `mod`-like words of the current mode
+pub fn tokenize(text: &str, word_operators: &[&str]) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let (token, len) = next_token(rest, c, word_operators);
+        tokens.push(token);
+        rest = rest[len..].trim_start();
+    }
+    tokens
+}
+
+
+
+fn next_token(text: &str, c: char, word_operators: &[&str]) -> (Token, usize) {
+    if let Some(operator) = LONG_SYMBOL_OPERATORS.iter().find(|operator| text.starts_with(*operator)) {
+        return (Token::Operator(operator.to_string()), operator.len());
+    }
+    if c.is_ascii_digit() || c == '.' {
+        let len = number_length(text);
+        return (Token::Number(text[..len].to_string()), len);
+    }
+    // `mod3` is `mod` and `3`, as the parser reads it
+    if let Some(word) = word_operators.iter().find(|word| word.starts_with(|c: char| c.is_ascii_alphabetic()) && text.starts_with(*word)) {
+        return (Token::Operator(word.to_string()), word.len());
+    }
+    if is_identifier_char(c) {
+        let len = text.find(|c: char| !is_identifier_char(c)).unwrap_or(text.len());
+        let name = text[..len].to_string();
+        if text[len..].starts_with('(') {
+            return (Token::Function(name), len + 1);
+        }
+        if name == ANSWER {
+            return (Token::Answer, len);
+        }
+        return (Token::Name(name), len);
+    }
+
+    let token = match c {
+        '(' => Token::OpenParen,
+        ')' => Token::CloseParen,
+        '%' => Token::Percent,
+        '!' => Token::Factorial,
+        '°' => Token::Degree,
+        ',' => Token::Separator,
+        '=' => Token::Assign,
+        '#' => Token::HistoryReference,
+        c if OPERATOR_SYMBOLS.contains(c) => Token::Operator(c.to_string()),
+        c => Token::Name(c.to_string()),
+    };
+    (token, c.len_utf8())
+}
+
+
+
+// `12.5`, `1.5e-7`, `0xFF`, a bare `0x`; an `e` without digits after it is the constant
+fn number_length(text: &str) -> usize {
+    let bytes = text.as_bytes();
+    if bytes.len() >= 2 && bytes[0] == b'0' && radix_of_prefix(bytes[1]).is_some() {
+        return 2 + text[2..].find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(text.len() - 2);
+    }
+
+    let mantissa = text.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(text.len());
+    let exponent = &bytes[mantissa..];
+    let sign = usize::from(exponent.get(1).is_some_and(|&b| b == b'+' || b == b'-'));
+    if exponent.first().is_some_and(|&b| b == b'e' || b == b'E') && exponent.get(1 + sign).is_some_and(u8::is_ascii_digit) {
+        let digits = text[mantissa + 1 + sign..].find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len() - mantissa - 1 - sign);
+        return mantissa + 1 + sign + digits;
+    }
+    mantissa
+}
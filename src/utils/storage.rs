@@ -0,0 +1,48 @@
+//*          Storage          *//
+//*   Developer: Urban Egor   *//
+//*   Version: 1.0.0 r        *//
+
+
+
+use std::fs::{self, create_dir_all};
+use std::path::{Path, PathBuf};
+
+use crate::utils::logger::LOGGER;
+
+
+const DATA_DIR: &str = "data";
+
+
+
+fn file_path(name: &str) -> PathBuf {
+    Path::new(DATA_DIR).join(format!("{}.txt", name))
+}
+
+
+
+// One `key=value` pair per line; a missing file is just an empty store
+pub fn load(name: &str) -> Vec<(String, String)> {
+    let Ok(content) = fs::read_to_string(file_path(name)) else {
+        return Vec::new();
+    };
+
+    content
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        .collect()
+}
+
+
+
+pub fn save(name: &str, entries: &[(String, String)]) {
+    if let Err(e) = create_dir_all(DATA_DIR) {
+        LOGGER.error(&format!("Cant create data dir: {}", e));
+        return;
+    }
+
+    let content: String = entries.iter().map(|(key, value)| format!("{}={}\n", key, value)).collect();
+    if let Err(e) = fs::write(file_path(name), content) {
+        LOGGER.error(&format!("Cant save {}: {}", name, e));
+    }
+}